@@ -0,0 +1,43 @@
+//! Prints how the railways of a map file are connected through shared stations.
+//!
+//! Usage: `connectivity <map file> [max distance]`, where pairs of stations within the maximum
+//! distance (in the logical coordinate, 100 by default) are reported as unlinked.
+
+use rerail_internal::RerailMap;
+use std::process::ExitCode;
+
+const DEFAULT_MAX_DISTANCE: f64 = 100.0;
+
+fn main() -> ExitCode {
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("Usage: {} <map file> [max distance]", args[0]);
+        return ExitCode::FAILURE;
+    }
+
+    let max_distance = match args.get(2).map(|s| s.parse::<f64>()) {
+        None => DEFAULT_MAX_DISTANCE,
+        Some(Ok(d)) if d.is_finite() && d >= 0.0 => d,
+        Some(_) => {
+            eprintln!("Invalid max distance: {}", args[2]);
+            return ExitCode::FAILURE;
+        }
+    };
+    let data = match std::fs::read(&args[1]) {
+        Ok(data) if data.starts_with(b"RM") || data.starts_with(b"RL") => data,
+        Ok(_) => {
+            eprintln!("{}: not a map file", args[1]);
+            return ExitCode::FAILURE;
+        }
+        Err(e) => {
+            eprintln!("{}: {}", args[1], e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let map = RerailMap::load(&data);
+    for line in map.connectivity_summary(max_distance) {
+        println!("{}", line);
+    }
+    ExitCode::SUCCESS
+}
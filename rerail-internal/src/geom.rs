@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
+use tsify::Tsify;
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
 pub struct Coord {
     pub x: i32,
     pub y: i32,
@@ -20,54 +22,83 @@ pub struct Rect {
     right: i32,
 }
 
-fn normalize_i32_f64(x: i32, y: i32) -> (f64, f64) {
-    let x = x as f64;
-    let y = y as f64;
+// All arithmetic on coordinates is done in wider types (i64 / i128 / f64) so that any pair of
+// i32 coordinates can be handled without overflow.
+
+fn diff_f64(p: Coord, q: Coord) -> (f64, f64) {
+    (p.x as f64 - q.x as f64, p.y as f64 - q.y as f64)
+}
+
+fn normalize_f64((x, y): (f64, f64)) -> (f64, f64) {
     let dist = (x * x + y * y).sqrt();
     (x / dist, y / dist)
 }
 
-pub fn compute_station_line_segment(
+/// Returns the direction (as a unit vector) of the railway passing `prev`, `cur` and `next` at `cur`,
+/// or `None` if it is not defined because neither `prev` nor `next` differs from `cur`.
+pub fn railway_direction(
     prev: Option<Coord>,
     cur: Coord,
     next: Option<Coord>,
-    station_length: i32,
-) -> (Coord, Coord) {
-    let (prev_dx, prev_dy, next_dx, next_dy) = match (prev, next) {
-        (Some(prev), Some(next)) => (
-            cur.x - prev.x,
-            cur.y - prev.y,
-            cur.x - next.x,
-            cur.y - next.y,
-        ),
-        (Some(prev), None) => (
-            cur.x - prev.x,
-            cur.y - prev.y,
-            prev.x - cur.x,
-            prev.y - cur.y,
-        ),
-        (None, Some(next)) => (
-            next.x - cur.x,
-            next.y - cur.y,
-            cur.x - next.x,
-            cur.y - next.y,
-        ),
-        (None, None) => panic!(),
+) -> Option<(f64, f64)> {
+    let prev = prev.filter(|&c| c != cur);
+    let next = next.filter(|&c| c != cur);
+
+    let (prev_d, next_d) = match (prev, next) {
+        (Some(prev), Some(next)) => (diff_f64(cur, prev), diff_f64(cur, next)),
+        (Some(prev), None) => (diff_f64(cur, prev), diff_f64(prev, cur)),
+        (None, Some(next)) => (diff_f64(next, cur), diff_f64(cur, next)),
+        (None, None) => return None,
     };
 
-    let (prev_dx_norm, prev_dy_norm) = normalize_i32_f64(prev_dx, prev_dy);
-    let (next_dx_norm, next_dy_norm) = normalize_i32_f64(next_dx, next_dy);
+    let (prev_dx_norm, prev_dy_norm) = normalize_f64(prev_d);
+    let (next_dx_norm, next_dy_norm) = normalize_f64(next_d);
 
     let dx = next_dx_norm - prev_dx_norm;
     let dy = next_dy_norm - prev_dy_norm;
     let d = (dx * dx + dy * dy).sqrt();
 
-    let dx = (dx / d * station_length as f64 * 0.5) as i32;
-    let dy = (dy / d * station_length as f64 * 0.5) as i32;
+    if d < 1e-9 {
+        // The railway turns back at `cur`
+        return Some((-prev_dx_norm, -prev_dy_norm));
+    }
+    Some((dx / d, dy / d))
+}
+
+/// Averages directions regarding `(x, y)` and `(-x, -y)` as the same direction.
+/// Returns `None` if `directions` is empty.
+pub fn average_direction(directions: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let first = *directions.first()?;
+
+    // Sum up the directions with their angles doubled so that opposite directions coincide
+    let mut cos2 = 0.0f64;
+    let mut sin2 = 0.0f64;
+    for &(x, y) in directions {
+        cos2 += x * x - y * y;
+        sin2 += 2.0 * x * y;
+    }
+    if cos2.abs() < 1e-9 && sin2.abs() < 1e-9 {
+        return Some(first);
+    }
+    let angle = sin2.atan2(cos2) * 0.5;
+    Some((angle.cos(), angle.sin()))
+}
+
+/// Computes the line segment representing a station at `cur` along `direction`.
+/// A horizontal segment is used if `direction` is not defined.
+pub fn compute_station_line_segment(
+    cur: Coord,
+    direction: Option<(f64, f64)>,
+    station_length: i32,
+) -> (Coord, Coord) {
+    let (dx, dy) = direction.unwrap_or((1.0, 0.0));
+
+    let dx = (dx * station_length as f64 * 0.5) as i32;
+    let dy = (dy * station_length as f64 * 0.5) as i32;
 
     (
-        Coord::new(cur.x - dx, cur.y - dy),
-        Coord::new(cur.x + dx, cur.y + dy),
+        Coord::new(cur.x.saturating_sub(dx), cur.y.saturating_sub(dy)),
+        Coord::new(cur.x.saturating_add(dx), cur.y.saturating_add(dy)),
     )
 }
 
@@ -90,26 +121,31 @@ fn line_segment_cross_with_vertical_line(
     if !between(ax, bx, x) {
         return false;
     }
+    let (ax, ay, bx, by) = (ax as i128, ay as i128, bx as i128, by as i128);
+    let (x, ylo, yhi) = (x as i128, ylo as i128, yhi as i128);
+
     // y = (x - ax) / (bx - ax) * (by - ay) + ay
-    let t = (x - ax) as i64 * (by - ay) as i64 + ay as i64 * (bx - ax) as i64;
+    let t = (x - ax) * (by - ay) + ay * (bx - ax);
     if ax < bx {
-        ylo as i64 * ((bx - ax) as i64) < t && t < yhi as i64 * (bx - ax) as i64
+        ylo * (bx - ax) < t && t < yhi * (bx - ax)
     } else {
-        ylo as i64 * ((bx - ax) as i64) > t && t > yhi as i64 * (bx - ax) as i64
+        ylo * (bx - ax) > t && t > yhi * (bx - ax)
     }
 }
 
-pub fn distance_norm_square_points(p: Coord, q: Coord) -> i64 {
-    let dx = (p.x - q.x) as i64;
-    let dy = (p.y - q.y) as i64;
+pub fn distance_norm_square_points(p: Coord, q: Coord) -> i128 {
+    let dx = p.x as i128 - q.x as i128;
+    let dy = p.y as i128 - q.y as i128;
     dx * dx + dy * dy
 }
 
-pub fn distance_norm_square_point_line_segment(p: Coord, q: Coord, a: Coord) -> i64 {
-    let tx = (q.x - p.x) as i64;
-    let x = (p.x - a.x) as i64;
-    let ty = (q.y - p.y) as i64;
-    let y = (p.y - a.y) as i64;
+/// Returns the square of the distance between the point `a` and the line segment `pq`, rounded up
+/// to an integer.
+pub fn distance_norm_square_point_line_segment(p: Coord, q: Coord, a: Coord) -> i128 {
+    let tx = q.x as i128 - p.x as i128;
+    let x = p.x as i128 - a.x as i128;
+    let ty = q.y as i128 - p.y as i128;
+    let y = p.y as i128 - a.y as i128;
 
     let a = tx * tx + ty * ty;
     let b = 2 * (tx * x + ty * y);
@@ -120,11 +156,260 @@ pub fn distance_norm_square_point_line_segment(p: Coord, q: Coord, a: Coord) ->
     } else if b <= -2 * a {
         a + b + c
     } else {
-        let w = (b as f64 * b as f64) / (a as f64) * 0.25;
-        c - w as i64
+        // c - b^2 / 4a, which equals (the cross product of (tx, ty) and (x, y))^2 / a
+        // (computed in f64 since the square may not fit in i128). It is rounded up so that a point
+        // off the segment never reads as being on it.
+        let cross = (tx * y - ty * x) as f64;
+        ((cross * cross / a as f64).ceil() as i128)
+            .min(c)
+            .min(a + b + c)
     }
 }
 
+fn round_to_coord(x: f64, y: f64) -> Coord {
+    // `as` saturates at the bounds of i32
+    Coord::new(x.round() as i32, y.round() as i32)
+}
+
+/// Returns the point on the line segment `pq` nearest to `a`, rounded to the integer coordinate.
+pub fn nearest_point_on_line_segment(p: Coord, q: Coord, a: Coord) -> Coord {
+    let (tx, ty) = diff_f64(q, p);
+    let (ax, ay) = diff_f64(a, p);
+    let len_sq = tx * tx + ty * ty;
+    if len_sq == 0.0 {
+        return p;
+    }
+    let t = ((ax * tx + ay * ty) / len_sq).clamp(0.0, 1.0);
+    round_to_coord(p.x as f64 + tx * t, p.y as f64 + ty * t)
+}
+
+/// Moves `a` to the nearest point on the lines through `origin` in the directions of multiples of
+/// 45 degrees.
+pub fn snap_to_45_degrees(origin: Coord, a: Coord) -> Coord {
+    let (dx, dy) = diff_f64(a, origin);
+    let angle = (dy.atan2(dx) / std::f64::consts::FRAC_PI_4).round() * std::f64::consts::FRAC_PI_4;
+    let (ux, uy) = (angle.cos(), angle.sin());
+    let len = dx * ux + dy * uy;
+    round_to_coord(origin.x as f64 + ux * len, origin.y as f64 + uy * len)
+}
+
+// Exponent of the knot intervals of Catmull-Rom splines. 0.5 gives the centripetal variant, which
+// forms neither cusps nor self-intersections within a segment.
+const CATMULL_ROM_ALPHA: f64 = 0.5;
+
+fn knot_interval(p: (f64, f64), q: (f64, f64)) -> f64 {
+    ((p.0 - q.0).powi(2) + (p.1 - q.1).powi(2)).powf(CATMULL_ROM_ALPHA * 0.5)
+}
+
+fn lerp(p: (f64, f64), q: (f64, f64), s: f64) -> (f64, f64) {
+    (p.0 + (q.0 - p.0) * s, p.1 + (q.1 - p.1) * s)
+}
+
+fn reflect(p: (f64, f64), center: (f64, f64)) -> (f64, f64) {
+    (2.0 * center.0 - p.0, 2.0 * center.1 - p.1)
+}
+
+/// Evaluates the centripetal Catmull-Rom spline through `points` into a polyline. `before` and
+/// `after` are the control points beyond the ends; the ends are extended straight if they are
+/// `None`. The `k`-th segment is divided into `divisions[k]` pieces, so the `k`-th point of `points`
+/// is found at the index `divisions[..k].sum()` of the result.
+pub fn catmull_rom_spline(
+    points: &[(f64, f64)],
+    before: Option<(f64, f64)>,
+    after: Option<(f64, f64)>,
+    divisions: &[usize],
+) -> Vec<(f64, f64)> {
+    assert_eq!(points.len(), divisions.len() + 1);
+
+    let mut ret = vec![];
+    for k in 0..divisions.len() {
+        let (p1, p2) = (points[k], points[k + 1]);
+        ret.push(p1);
+        if p1 == p2 {
+            ret.extend(std::iter::repeat_n(p1, divisions[k] - 1));
+            continue;
+        }
+
+        // Control points coinciding with the ends of the segment are replaced by reflections
+        let p0 = match if k == 0 { before } else { Some(points[k - 1]) } {
+            Some(p0) if p0 != p1 => p0,
+            _ => reflect(p2, p1),
+        };
+        let p3 = match points.get(k + 2).copied().or(after) {
+            Some(p3) if p3 != p2 => p3,
+            _ => reflect(p1, p2),
+        };
+
+        let t1 = knot_interval(p0, p1);
+        let t2 = t1 + knot_interval(p1, p2);
+        let t3 = t2 + knot_interval(p2, p3);
+        for d in 1..divisions[k] {
+            let t = t1 + (t2 - t1) * d as f64 / divisions[k] as f64;
+            let a1 = lerp(p0, p1, t / t1);
+            let a2 = lerp(p1, p2, (t - t1) / (t2 - t1));
+            let a3 = lerp(p2, p3, (t - t2) / (t3 - t2));
+            let b1 = lerp(a1, a2, t / t2);
+            let b2 = lerp(a2, a3, (t - t1) / (t3 - t1));
+            ret.push(lerp(b1, b2, (t - t1) / (t2 - t1)));
+        }
+    }
+    ret.push(points[points.len() - 1]);
+
+    ret
+}
+
+/// Returns the direction (as a unit vector) at `cur` of the centripetal Catmull-Rom spline passing
+/// `prev`, `cur` and `next`, or `None` if it is not defined in the same way as `railway_direction`.
+pub fn catmull_rom_direction(
+    prev: Option<Coord>,
+    cur: Coord,
+    next: Option<Coord>,
+) -> Option<(f64, f64)> {
+    let (prev, next) = match (prev.filter(|&c| c != cur), next.filter(|&c| c != cur)) {
+        (Some(prev), Some(next)) if prev != next => (prev, next),
+        // The spline is straight at the ends
+        (prev, next) => return railway_direction(prev, cur, next),
+    };
+
+    let to_f64 = |c: Coord| (c.x as f64, c.y as f64);
+    let (p0, p1, p2) = (to_f64(prev), to_f64(cur), to_f64(next));
+    let t01 = knot_interval(p0, p1);
+    let t12 = knot_interval(p1, p2);
+
+    // Derivative of the spline at `cur`, up to a positive factor
+    let dx = (p1.0 - p0.0) / t01 - (p2.0 - p0.0) / (t01 + t12) + (p2.0 - p1.0) / t12;
+    let dy = (p1.1 - p0.1) / t01 - (p2.1 - p0.1) / (t01 + t12) + (p2.1 - p1.1) / t12;
+    let d = (dx * dx + dy * dy).sqrt();
+    if d < 1e-9 {
+        return railway_direction(Some(prev), cur, Some(next));
+    }
+    Some((dx / d, dy / d))
+}
+
+// Offset lines meeting farther than this times the offset from the vertex are not mitered
+const MITER_LIMIT: f64 = 4.0;
+
+/// Offsets the polyline `points` perpendicularly by `offsets[k]` for the `k`-th segment, to the
+/// side of `(-dy, dx)` for the direction `(dx, dy)` of the segment. Adjacent segments are joined at
+/// the intersection of their offset lines, or by both offset endpoints if they are (nearly)
+/// parallel or the intersection is too far.
+pub fn offset_polyline(points: &[(f64, f64)], offsets: &[f64]) -> Vec<(f64, f64)> {
+    assert_eq!(points.len(), offsets.len() + 1);
+    if offsets.iter().all(|&o| o == 0.0) {
+        return points.to_vec();
+    }
+
+    // (start, end, unit direction, offset) of the offset segments, skipping degenerate ones
+    let mut segments = vec![];
+    for k in 0..offsets.len() {
+        let (a, b) = (points[k], points[k + 1]);
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let len = (dx * dx + dy * dy).sqrt();
+        if len == 0.0 {
+            continue;
+        }
+        let (ux, uy) = (dx / len, dy / len);
+        let (nx, ny) = (-uy * offsets[k], ux * offsets[k]);
+        segments.push((
+            (a.0 + nx, a.1 + ny),
+            (b.0 + nx, b.1 + ny),
+            (ux, uy),
+            offsets[k],
+        ));
+    }
+    if segments.is_empty() {
+        return points.to_vec();
+    }
+
+    let mut ret = vec![segments[0].0];
+    for w in segments.windows(2) {
+        let (_, p1, d1, o1) = w[0];
+        let (p2, _, d2, o2) = w[1];
+        let cross = d1.0 * d2.1 - d1.1 * d2.0;
+        let join = if cross.abs() < 1e-9 {
+            None
+        } else {
+            let t = ((p2.0 - p1.0) * d2.1 - (p2.1 - p1.1) * d2.0) / cross;
+            let q = (p1.0 + d1.0 * t, p1.1 + d1.1 * t);
+            let dist_sq = (q.0 - p1.0).powi(2) + (q.1 - p1.1).powi(2);
+            let limit = MITER_LIMIT * o1.abs().max(o2.abs());
+            (dist_sq <= limit * limit).then_some(q)
+        };
+        match join {
+            Some(q) => ret.push(q),
+            None => {
+                ret.push(p1);
+                if p1 != p2 {
+                    ret.push(p2);
+                }
+            }
+        }
+    }
+    ret.push(segments[segments.len() - 1].1);
+
+    ret
+}
+
+/// Simplifies the polyline by the Douglas-Peucker algorithm and returns the indices of the retained points.
+/// Both endpoints and the points `i` with `keep[i]` set are always retained.
+pub fn simplify_polyline(points: &[Coord], keep: &[bool], tolerance: f64) -> Vec<usize> {
+    assert_eq!(points.len(), keep.len());
+    if points.len() <= 2 {
+        return (0..points.len()).collect();
+    }
+
+    let tolerance_sq = tolerance * tolerance;
+    let mut retained = keep.to_vec();
+    retained[0] = true;
+    retained[points.len() - 1] = true;
+
+    let mut stack = vec![];
+    let mut last = 0;
+    for (i, &r) in retained.iter().enumerate().skip(1) {
+        if r {
+            stack.push((last, i));
+            last = i;
+        }
+    }
+
+    while let Some((lo, hi)) = stack.pop() {
+        let mut farthest = None;
+        for i in (lo + 1)..hi {
+            let d = distance_norm_square_point_line_segment(points[lo], points[hi], points[i]);
+            if farthest.map(|(d2, _)| d > d2).unwrap_or(true) {
+                farthest = Some((d, i));
+            }
+        }
+        if let Some((d, i)) = farthest {
+            if d as f64 > tolerance_sq {
+                retained[i] = true;
+                stack.push((lo, i));
+                stack.push((i, hi));
+            }
+        }
+    }
+
+    (0..points.len()).filter(|&i| retained[i]).collect()
+}
+
+/// Returns whether `p` is inside the polygon by the even-odd rule.
+/// Points on the boundary may be regarded as either inside or outside.
+pub fn polygon_contains(polygon: &[Coord], p: Coord) -> bool {
+    let mut inside = false;
+    for (i, &a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        if (a.y > p.y) != (b.y > p.y) {
+            // Whether the edge crosses the horizontal ray from `p` to the right
+            let lhs = (p.y as i128 - a.y as i128) * (b.x as i128 - a.x as i128);
+            let rhs = (p.x as i128 - a.x as i128) * (b.y as i128 - a.y as i128);
+            if (b.y > a.y) == (lhs > rhs) {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
 impl Rect {
     pub fn new(top: i32, bottom: i32, left: i32, right: i32) -> Rect {
         Rect {
@@ -135,7 +420,6 @@ impl Rect {
         }
     }
 
-    #[allow(unused)]
     pub fn from_corners(a: Coord, b: Coord) -> Rect {
         Rect {
             top: a.y.min(b.y),
@@ -145,6 +429,14 @@ impl Rect {
         }
     }
 
+    pub fn top_left(&self) -> Coord {
+        Coord::new(self.left, self.top)
+    }
+
+    pub fn bottom_right(&self) -> Coord {
+        Coord::new(self.right, self.bottom)
+    }
+
     pub fn contains(&self, pt: Coord) -> bool {
         self.top < pt.y && pt.y < self.bottom && self.left < pt.x && pt.x < self.right
     }
@@ -207,7 +499,26 @@ impl Rect {
 mod tests {
     use super::*;
 
+    const EXTREME_VALUES: [i32; 7] = [i32::MIN, i32::MIN + 1, -1, 0, 1, i32::MAX - 1, i32::MAX];
+
+    // Coordinates over the full i32 range, including extreme values
+    fn arbitrary_coords(n: usize) -> Vec<Coord> {
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            if state & 3 == 0 {
+                EXTREME_VALUES[(state >> 8) as usize % EXTREME_VALUES.len()]
+            } else {
+                (state >> 16) as u32 as i32
+            }
+        };
+        (0..n).map(|_| Coord::new(next(), next())).collect()
+    }
+
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_rect() {
         let rect = Rect::new(-1, 4, 2, 5);
 
@@ -253,4 +564,307 @@ mod tests {
             false
         );
     }
+
+    #[test]
+    fn test_simplify_polyline() {
+        let points = [
+            Coord::new(0, 0),
+            Coord::new(1, 0),
+            Coord::new(2, 1),
+            Coord::new(3, 0),
+            Coord::new(10, 0),
+            Coord::new(10, 5),
+            Coord::new(10, 10),
+        ];
+
+        assert_eq!(
+            simplify_polyline(&points, &[false; 7], 0.5),
+            vec![0, 1, 2, 3, 4, 6]
+        );
+        assert_eq!(simplify_polyline(&points, &[false; 7], 1.5), vec![0, 4, 6]);
+        assert_eq!(
+            simplify_polyline(
+                &points,
+                &[false, true, false, false, false, true, false],
+                1.5
+            ),
+            vec![0, 1, 4, 5, 6]
+        );
+        assert_eq!(
+            simplify_polyline(&points[..2], &[false; 2], 100.0),
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn test_distance_full_range() {
+        let coords = arbitrary_coords(3000);
+        for w in coords.windows(3) {
+            let (p, q, a) = (w[0], w[1], w[2]);
+
+            let dx = p.x as f64 - q.x as f64;
+            let dy = p.y as f64 - q.y as f64;
+            let expected = dx * dx + dy * dy;
+            let actual = distance_norm_square_points(p, q) as f64;
+            assert!((actual - expected).abs() <= expected * 1e-12);
+
+            let d = distance_norm_square_point_line_segment(p, q, a);
+            assert!(0 <= d);
+            assert!(d <= distance_norm_square_points(p, a));
+            assert!(d <= distance_norm_square_points(q, a));
+        }
+
+        let min = Coord::new(i32::MIN, i32::MIN);
+        let max = Coord::new(i32::MAX, i32::MAX);
+        assert_eq!(
+            distance_norm_square_points(min, max),
+            2 * (u32::MAX as i128) * (u32::MAX as i128)
+        );
+        assert_eq!(
+            distance_norm_square_point_line_segment(min, max, Coord::new(0, 0)),
+            0
+        );
+    }
+
+    #[test]
+    fn test_distance_point_line_segment_rounding() {
+        // Points less than one unit off a segment are not on it
+        let d = |p: (i32, i32), q: (i32, i32), a: (i32, i32)| {
+            distance_norm_square_point_line_segment(
+                Coord::new(p.0, p.1),
+                Coord::new(q.0, q.1),
+                Coord::new(a.0, a.1),
+            )
+        };
+        assert_eq!(d((0, 0), (1000, 999), (1, 0)), 1);
+        assert_eq!(d((2, 1), (10, 0), (3, 0)), 1);
+        assert_eq!(d((0, 0), (10, 0), (5, 0)), 0);
+        assert_eq!(d((0, 0), (10, 0), (5, 3)), 9);
+        assert_eq!(d((0, 0), (10, 0), (13, 4)), 25);
+    }
+
+    #[test]
+    fn test_snap_geometry() {
+        let p = Coord::new(0, 0);
+        let q = Coord::new(100, 0);
+        assert!(nearest_point_on_line_segment(p, q, Coord::new(30, 20)) == Coord::new(30, 0));
+        assert!(nearest_point_on_line_segment(p, q, Coord::new(-30, 20)) == p);
+        assert!(nearest_point_on_line_segment(p, q, Coord::new(130, 20)) == q);
+        assert!(nearest_point_on_line_segment(p, p, Coord::new(130, 20)) == p);
+
+        assert!(snap_to_45_degrees(p, Coord::new(100, 3)) == Coord::new(100, 0));
+        assert!(snap_to_45_degrees(p, Coord::new(-3, 100)) == Coord::new(0, 100));
+        assert!(snap_to_45_degrees(p, Coord::new(-48, -52)) == Coord::new(-50, -50));
+        assert!(snap_to_45_degrees(p, p) == p);
+
+        let a = Coord::new(i32::MIN, i32::MIN);
+        let b = Coord::new(i32::MAX, i32::MAX);
+        assert!(
+            nearest_point_on_line_segment(a, b, Coord::new(i32::MAX, i32::MIN))
+                == Coord::new(-1, -1)
+        );
+        assert!(
+            snap_to_45_degrees(a, Coord::new(i32::MAX, i32::MIN + 1))
+                == Coord::new(i32::MAX, i32::MIN)
+        );
+    }
+
+    #[test]
+    fn test_polygon_contains() {
+        // A concave polygon shaped like "U"
+        let polygon = [
+            Coord::new(0, 0),
+            Coord::new(10, 0),
+            Coord::new(10, 30),
+            Coord::new(20, 30),
+            Coord::new(20, 0),
+            Coord::new(30, 0),
+            Coord::new(30, 40),
+            Coord::new(0, 40),
+        ];
+        assert!(polygon_contains(&polygon, Coord::new(5, 20)));
+        assert!(polygon_contains(&polygon, Coord::new(15, 35)));
+        assert!(polygon_contains(&polygon, Coord::new(25, 1)));
+        assert!(!polygon_contains(&polygon, Coord::new(15, 20)));
+        assert!(!polygon_contains(&polygon, Coord::new(-5, 20)));
+        assert!(!polygon_contains(&polygon, Coord::new(5, 41)));
+
+        // Reversed orientation
+        let reversed = polygon.iter().rev().copied().collect::<Vec<_>>();
+        assert!(polygon_contains(&reversed, Coord::new(5, 20)));
+        assert!(!polygon_contains(&reversed, Coord::new(15, 20)));
+
+        let large = [
+            Coord::new(i32::MIN, i32::MIN),
+            Coord::new(i32::MAX, i32::MIN),
+            Coord::new(i32::MAX, i32::MAX),
+        ];
+        assert!(polygon_contains(&large, Coord::new(i32::MAX - 1, 0)));
+        assert!(!polygon_contains(&large, Coord::new(i32::MIN + 1, 0)));
+        assert!(!polygon_contains(&[], Coord::new(0, 0)));
+    }
+
+    #[test]
+    fn test_rect_full_range() {
+        let coords = arbitrary_coords(3000);
+        for w in coords.windows(4) {
+            let rect = Rect::from_corners(w[0], w[1]);
+            let (a, b) = (w[2], w[3]);
+            let crosses = rect.crosses_with_line_segment(a, b);
+            assert_eq!(crosses, rect.crosses_with_line_segment(b, a));
+            if rect.contains(a) || rect.contains(b) {
+                assert!(crosses);
+            }
+        }
+
+        let rect = Rect::new(i32::MIN + 1, i32::MAX - 1, i32::MIN + 1, i32::MAX - 1);
+        assert!(rect.crosses_with_line_segment(Coord::new(i32::MIN, 0), Coord::new(i32::MAX, 1)));
+        assert!(rect.crosses_with_line_segment(
+            Coord::new(i32::MIN, i32::MAX - 1),
+            Coord::new(i32::MAX, i32::MIN + 1)
+        ));
+    }
+
+    #[test]
+    fn test_station_line_segment_full_range() {
+        let coords = arbitrary_coords(3000);
+        for w in coords.windows(3) {
+            for (prev, next) in [
+                (Some(w[0]), Some(w[2])),
+                (None, Some(w[2])),
+                (Some(w[0]), None),
+            ] {
+                let dir = railway_direction(prev, w[1], next);
+                if let Some((dx, dy)) = dir {
+                    assert!(dx.is_finite() && dy.is_finite());
+                }
+                compute_station_line_segment(w[1], dir, i32::MAX);
+            }
+        }
+
+        let cur = Coord::new(i32::MAX, i32::MAX);
+        let dir = railway_direction(Some(Coord::new(i32::MIN, i32::MAX)), cur, None);
+        let (c0, c1) = compute_station_line_segment(cur, dir, 200);
+        assert!(c0 == Coord::new(i32::MAX, i32::MAX));
+        assert!(c1 == Coord::new(i32::MAX - 100, i32::MAX));
+    }
+
+    #[test]
+    fn test_station_line_segment_degenerate() {
+        let origin = Coord::new(0, 0);
+
+        // Single point railway
+        assert!(railway_direction(None, origin, None).is_none());
+        let (c0, c1) = compute_station_line_segment(origin, None, 200);
+        assert!(c0 == Coord::new(-100, 0) && c1 == Coord::new(100, 0));
+
+        // Duplicated points
+        assert!(railway_direction(Some(origin), origin, Some(origin)).is_none());
+        let dir = railway_direction(Some(origin), origin, Some(Coord::new(0, 10))).unwrap();
+        assert!(dir.0.abs() < 1e-9 && (dir.1.abs() - 1.0).abs() < 1e-9);
+
+        // 180 degree turn
+        let dir =
+            railway_direction(Some(Coord::new(10, 0)), origin, Some(Coord::new(20, 0))).unwrap();
+        assert!((dir.0.abs() - 1.0).abs() < 1e-9 && dir.1.abs() < 1e-9);
+
+        // Straight and right-angled railways
+        let dir =
+            railway_direction(Some(Coord::new(-10, 0)), origin, Some(Coord::new(10, 0))).unwrap();
+        assert!((dir.0.abs() - 1.0).abs() < 1e-9 && dir.1.abs() < 1e-9);
+        let dir =
+            railway_direction(Some(Coord::new(-10, 0)), origin, Some(Coord::new(0, 10))).unwrap();
+        assert!((dir.0.abs() - dir.1.abs()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_average_direction() {
+        assert!(average_direction(&[]).is_none());
+
+        // Opposite directions are regarded as the same
+        let dir = average_direction(&[(1.0, 0.0), (-1.0, 0.0)]).unwrap();
+        assert!((dir.0.abs() - 1.0).abs() < 1e-9 && dir.1.abs() < 1e-9);
+
+        let s = 0.5f64.sqrt();
+        let dir = average_direction(&[(1.0, 0.0), (0.0, -1.0), (s, s)]).unwrap();
+        assert!((dir.0.abs() - s).abs() < 1e-9 && (dir.1.abs() - s).abs() < 1e-9);
+
+        // Perpendicular directions cancel out; the first one is used
+        let dir = average_direction(&[(1.0, 0.0), (0.0, 1.0)]).unwrap();
+        assert!(dir == (1.0, 0.0));
+    }
+
+    #[test]
+    fn test_offset_polyline() {
+        let points = [(0.0, 0.0), (10.0, 0.0), (20.0, 0.0)];
+        assert_eq!(offset_polyline(&points, &[0.0, 0.0]), points.to_vec());
+        assert_eq!(
+            offset_polyline(&points, &[1.0, 1.0]),
+            vec![(0.0, 1.0), (10.0, 1.0), (20.0, 1.0)]
+        );
+        // The offset changes at the vertex
+        assert_eq!(
+            offset_polyline(&points, &[1.0, 2.0]),
+            vec![(0.0, 1.0), (10.0, 1.0), (10.0, 2.0), (20.0, 2.0)]
+        );
+
+        let points = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)];
+        assert_eq!(
+            offset_polyline(&points, &[1.0, 1.0]),
+            vec![(0.0, 1.0), (9.0, 1.0), (9.0, 10.0)]
+        );
+
+        // Degenerate segments are ignored
+        let points = [(0.0, 0.0), (0.0, 0.0), (10.0, 0.0)];
+        assert_eq!(
+            offset_polyline(&points, &[5.0, -1.0]),
+            vec![(0.0, -1.0), (10.0, -1.0)]
+        );
+
+        // A sharp turn is not mitered
+        let points = [(0.0, 0.0), (10.0, 0.0), (0.0, 0.1)];
+        assert_eq!(offset_polyline(&points, &[1.0, 1.0]).len(), 4);
+    }
+
+    #[test]
+    fn test_catmull_rom_spline() {
+        // A straight polyline stays straight
+        let points = [(0.0, 0.0), (10.0, 0.0), (30.0, 0.0)];
+        let curve = catmull_rom_spline(&points, None, None, &[2, 4]);
+        assert_eq!(curve.len(), 7);
+        assert!(curve[0] == (0.0, 0.0) && curve[2] == (10.0, 0.0) && curve[6] == (30.0, 0.0));
+        assert!(curve
+            .windows(2)
+            .all(|w| w[0].0 < w[1].0 && w[1].1.abs() < 1e-9));
+
+        // The curve passes the control points and bulges outward at the corner
+        let points = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)];
+        let curve = catmull_rom_spline(&points, None, None, &[4, 4]);
+        assert_eq!(curve[4], (10.0, 0.0));
+        assert!(curve[2].1 < 0.0 && curve[6].0 > 10.0);
+
+        // Duplicated points do not break the evaluation
+        let points = [(0.0, 0.0), (0.0, 0.0), (10.0, 0.0), (10.0, 0.0)];
+        let curve = catmull_rom_spline(&points, Some((0.0, 0.0)), None, &[3, 3, 3]);
+        assert_eq!(curve.len(), 10);
+        assert!(curve.iter().all(|p| p.0.is_finite() && p.1.abs() < 1e-9));
+
+        let dir = catmull_rom_direction(
+            Some(Coord::new(0, 0)),
+            Coord::new(10, 0),
+            Some(Coord::new(10, 10)),
+        )
+        .unwrap();
+        assert!((dir.0 - dir.1).abs() < 1e-9 && dir.0 > 0.0);
+        // Closer points dominate the direction
+        let dir = catmull_rom_direction(
+            Some(Coord::new(0, 0)),
+            Coord::new(100, 0),
+            Some(Coord::new(100, 1)),
+        )
+        .unwrap();
+        assert!(dir.1 > dir.0);
+        let dir = catmull_rom_direction(None, Coord::new(0, 0), Some(Coord::new(0, 5))).unwrap();
+        assert!(dir.0 == 0.0 && dir.1.abs() == 1.0);
+    }
 }
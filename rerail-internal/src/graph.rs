@@ -0,0 +1,132 @@
+/// Returns the id of the connected component of each vertex of the undirected graph given by the
+/// adjacency lists. Components are numbered in the order of their smallest vertices.
+pub fn connected_components(adj: &[Vec<usize>]) -> Vec<usize> {
+    let mut component = vec![usize::MAX; adj.len()];
+    let mut num_components = 0;
+
+    for s in 0..adj.len() {
+        if component[s] != usize::MAX {
+            continue;
+        }
+        component[s] = num_components;
+        let mut stack = vec![s];
+        while let Some(v) = stack.pop() {
+            for &w in &adj[v] {
+                if component[w] == usize::MAX {
+                    component[w] = num_components;
+                    stack.push(w);
+                }
+            }
+        }
+        num_components += 1;
+    }
+
+    component
+}
+
+/// Returns whether each vertex of the undirected graph given by the adjacency lists is an
+/// articulation point, whose removal increases the number of connected components.
+pub fn articulation_points(adj: &[Vec<usize>]) -> Vec<bool> {
+    let n = adj.len();
+    let mut order = vec![usize::MAX; n];
+    let mut low = vec![0; n];
+    let mut is_articulation = vec![false; n];
+    let mut next_order = 0;
+
+    // Depth-first search without recursion, as railway networks can be deep
+    for root in 0..n {
+        if order[root] != usize::MAX {
+            continue;
+        }
+        order[root] = next_order;
+        low[root] = next_order;
+        next_order += 1;
+        let mut root_children = 0;
+
+        // (vertex, parent, index of the next edge to visit)
+        let mut stack = vec![(root, usize::MAX, 0)];
+        while let Some(&mut (v, parent, ref mut k)) = stack.last_mut() {
+            if let Some(&w) = adj[v].get(*k) {
+                *k += 1;
+                if order[w] == usize::MAX {
+                    order[w] = next_order;
+                    low[w] = next_order;
+                    next_order += 1;
+                    if v == root {
+                        root_children += 1;
+                    }
+                    stack.push((w, v, 0));
+                } else if w != parent {
+                    low[v] = low[v].min(order[w]);
+                }
+                continue;
+            }
+
+            stack.pop();
+            if parent != usize::MAX {
+                low[parent] = low[parent].min(low[v]);
+                if parent != root && low[v] >= order[parent] {
+                    is_articulation[parent] = true;
+                }
+            }
+        }
+        is_articulation[root] = root_children >= 2;
+    }
+
+    is_articulation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn undirected(n: usize, edges: &[(usize, usize)]) -> Vec<Vec<usize>> {
+        let mut adj = vec![vec![]; n];
+        for &(a, b) in edges {
+            adj[a].push(b);
+            adj[b].push(a);
+        }
+        adj
+    }
+
+    #[test]
+    fn test_connected_components() {
+        let adj = undirected(6, &[(0, 2), (2, 4), (1, 3)]);
+        assert_eq!(connected_components(&adj), vec![0, 1, 0, 1, 0, 2]);
+        assert_eq!(connected_components(&[]), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_articulation_points() {
+        // Two triangles joined at 2, with a tail 4-5-6
+        let adj = undirected(
+            7,
+            &[
+                (0, 1),
+                (1, 2),
+                (2, 0),
+                (2, 3),
+                (3, 4),
+                (4, 2),
+                (4, 5),
+                (5, 6),
+            ],
+        );
+        assert_eq!(
+            articulation_points(&adj),
+            vec![false, false, true, false, true, true, false]
+        );
+
+        // A cycle has none; the root of a path is not one
+        let adj = undirected(4, &[(0, 1), (1, 2), (2, 3), (3, 0)]);
+        assert_eq!(articulation_points(&adj), vec![false; 4]);
+        let adj = undirected(3, &[(0, 1), (1, 2)]);
+        assert_eq!(articulation_points(&adj), vec![false, true, false]);
+
+        // A long path does not overflow the stack
+        let n = 100000;
+        let edges = (1..n).map(|i| (i - 1, i)).collect::<Vec<_>>();
+        let result = articulation_points(&undirected(n, &edges));
+        assert!(!result[0] && !result[n - 1] && result[1..n - 1].iter().all(|&a| a));
+    }
+}
@@ -1,5 +1,10 @@
 mod geom;
+mod graph;
 mod loader;
+mod projection;
 mod railway_map;
+mod search;
 mod sparse_array;
+mod spatial_index;
 pub use loader::load_legacy_railmap_file;
+pub use railway_map::RerailMap;
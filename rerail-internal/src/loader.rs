@@ -111,7 +111,7 @@ pub fn load_legacy_railmap_file<T: BufRead>(mut reader: &mut T) -> std::io::Resu
         }
 
         let rail_idx = rerail_map.new_railway(rail_name, rail_color, rail_level);
-        for (c, st) in points.into_iter().zip(associated_stations.into_iter()) {
+        for (c, st) in points.into_iter().zip(associated_stations) {
             if let Some(st) = &st {
                 rerail_map[*st].add_railway(rail_idx);
             }
@@ -170,5 +170,7 @@ pub fn load_legacy_railmap_file<T: BufRead>(mut reader: &mut T) -> std::io::Resu
         rerail_map[border_point_indices[v]].add_neighbor(border_point_indices[u], level);
     }
 
+    rerail_map.rebuild_geometry_index();
+
     Ok(rerail_map)
 }
@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+// Radius of the earth (in meters) assumed by the projections, the same as in Web Mercator
+const EARTH_RADIUS: f64 = 6378137.0;
+
+// Web Mercator is not defined beyond these latitudes
+const MAX_MERCATOR_LATITUDE: f64 = 85.05112878;
+
+#[derive(Clone, Copy, PartialEq, Debug, Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct GeoCoord {
+    /// Latitude in degrees
+    pub lat: f64,
+    /// Longitude in degrees
+    pub lon: f64,
+}
+
+/// Relation between the logical coordinate and the geographic coordinate.
+/// The logical x axis points to the east and the y axis to the south.
+#[derive(Clone, Copy, PartialEq, Debug, Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum Projection {
+    /// Longitudes and latitudes are scaled linearly, with the true scale along the parallel and
+    /// the meridian through `origin`, which is at the logical origin.
+    /// `scale` is the number of logical units per meter.
+    #[serde(rename = "equirectangular")]
+    Equirectangular { origin: GeoCoord, scale: f64 },
+    /// Web Mercator with `origin` at the logical origin. `scale` is the number of logical units per
+    /// meter on the equator.
+    #[serde(rename = "webMercator")]
+    WebMercator { origin: GeoCoord, scale: f64 },
+    /// `x = c[0] * lon + c[1] * lat + c[2]` and `y = c[3] * lon + c[4] * lat + c[5]`, typically
+    /// fitted to control points by `fit_affine`.
+    #[serde(rename = "affine")]
+    Affine { coefficients: [f64; 6] },
+}
+
+/// A pair of corresponding positions for fitting an affine projection.
+#[derive(Clone, Copy, Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct ControlPoint {
+    pub geo: GeoCoord,
+    pub x: f64,
+    pub y: f64,
+}
+
+fn mercator_y(lat: f64) -> f64 {
+    let lat = lat.clamp(-MAX_MERCATOR_LATITUDE, MAX_MERCATOR_LATITUDE);
+    (std::f64::consts::FRAC_PI_4 + lat.to_radians() * 0.5)
+        .tan()
+        .ln()
+}
+
+fn inverse_mercator_y(y: f64) -> f64 {
+    (2.0 * y.exp().atan() - std::f64::consts::FRAC_PI_2).to_degrees()
+}
+
+/// Solves the 3x3 linear system `a * x = b`, or returns `None` if `a` is (nearly) singular.
+fn solve3(mut a: [[f64; 3]; 3], mut b: [f64; 3]) -> Option<[f64; 3]> {
+    for col in 0..3 {
+        let pivot = (col..3).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col];
+        for row in 0..3 {
+            if row != col {
+                let f = a[row][col] / pivot_row[col];
+                for (v, p) in a[row].iter_mut().zip(pivot_row).skip(col) {
+                    *v -= f * p;
+                }
+                b[row] -= f * b[col];
+            }
+        }
+    }
+    Some([b[0] / a[0][0], b[1] / a[1][1], b[2] / a[2][2]])
+}
+
+impl Projection {
+    /// Position of `geo` in the logical coordinate.
+    pub fn project(&self, geo: GeoCoord) -> (f64, f64) {
+        match *self {
+            Projection::Equirectangular { origin, scale } => (
+                (geo.lon - origin.lon).to_radians()
+                    * origin.lat.to_radians().cos()
+                    * EARTH_RADIUS
+                    * scale,
+                -(geo.lat - origin.lat).to_radians() * EARTH_RADIUS * scale,
+            ),
+            Projection::WebMercator { origin, scale } => (
+                (geo.lon - origin.lon).to_radians() * EARTH_RADIUS * scale,
+                -(mercator_y(geo.lat) - mercator_y(origin.lat)) * EARTH_RADIUS * scale,
+            ),
+            Projection::Affine { coefficients: c } => (
+                c[0] * geo.lon + c[1] * geo.lat + c[2],
+                c[3] * geo.lon + c[4] * geo.lat + c[5],
+            ),
+        }
+    }
+
+    /// Geographic coordinate of `(x, y)` in the logical coordinate, or `None` if the projection is
+    /// degenerate.
+    pub fn unproject(&self, (x, y): (f64, f64)) -> Option<GeoCoord> {
+        match *self {
+            Projection::Equirectangular { origin, scale } => {
+                let k = origin.lat.to_radians().cos() * EARTH_RADIUS * scale;
+                if k == 0.0 || scale == 0.0 {
+                    return None;
+                }
+                Some(GeoCoord {
+                    lat: origin.lat - (y / (EARTH_RADIUS * scale)).to_degrees(),
+                    lon: origin.lon + (x / k).to_degrees(),
+                })
+            }
+            Projection::WebMercator { origin, scale } => {
+                if scale == 0.0 {
+                    return None;
+                }
+                let k = EARTH_RADIUS * scale;
+                Some(GeoCoord {
+                    lat: inverse_mercator_y(mercator_y(origin.lat) - y / k),
+                    lon: origin.lon + (x / k).to_degrees(),
+                })
+            }
+            Projection::Affine { coefficients: c } => {
+                let det = c[0] * c[4] - c[1] * c[3];
+                if det.abs() < 1e-12 {
+                    return None;
+                }
+                let (x, y) = (x - c[2], y - c[5]);
+                Some(GeoCoord {
+                    lon: (c[4] * x - c[1] * y) / det,
+                    lat: (c[0] * y - c[3] * x) / det,
+                })
+            }
+        }
+    }
+
+    /// Fits an affine projection to the control points by least squares. Returns `None` unless
+    /// there are at least three control points not on a line.
+    pub fn fit_affine(points: &[ControlPoint]) -> Option<Projection> {
+        // Normal equations of the least squares for each of x and y
+        let mut a = [[0.0; 3]; 3];
+        let mut bx = [0.0; 3];
+        let mut by = [0.0; 3];
+        for p in points {
+            let v = [p.geo.lon, p.geo.lat, 1.0];
+            for i in 0..3 {
+                for j in 0..3 {
+                    a[i][j] += v[i] * v[j];
+                }
+                bx[i] += v[i] * p.x;
+                by[i] += v[i] * p.y;
+            }
+        }
+
+        let cx = solve3(a, bx)?;
+        let cy = solve3(a, by)?;
+        Some(Projection::Affine {
+            coefficients: [cx[0], cx[1], cx[2], cy[0], cy[1], cy[2]],
+        })
+    }
+}
+
+/// Great-circle distance between `a` and `b` in meters.
+pub fn geodesic_distance(a: GeoCoord, b: GeoCoord) -> f64 {
+    let (lat1, lat2) = (a.lat.to_radians(), b.lat.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (b.lon - a.lon).to_radians();
+    let h = (dlat * 0.5).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon * 0.5).sin().powi(2);
+    2.0 * EARTH_RADIUS * h.sqrt().min(1.0).asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKYO: GeoCoord = GeoCoord {
+        lat: 35.681236,
+        lon: 139.767125,
+    };
+    const OSAKA: GeoCoord = GeoCoord {
+        lat: 34.702485,
+        lon: 135.495951,
+    };
+
+    fn assert_geo_eq(a: GeoCoord, b: GeoCoord) {
+        assert!(
+            (a.lat - b.lat).abs() < 1e-9 && (a.lon - b.lon).abs() < 1e-9,
+            "{:?} {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn test_projection_round_trip() {
+        let projections = [
+            Projection::Equirectangular {
+                origin: TOKYO,
+                scale: 10.0,
+            },
+            Projection::WebMercator {
+                origin: TOKYO,
+                scale: 10.0,
+            },
+            Projection::Affine {
+                coefficients: [1000.0, 10.0, -5.0, 20.0, -1200.0, 7.0],
+            },
+        ];
+        for projection in projections {
+            for geo in [TOKYO, OSAKA] {
+                let p = projection.project(geo);
+                assert_geo_eq(projection.unproject(p).unwrap(), geo);
+            }
+        }
+
+        let origin = Projection::WebMercator {
+            origin: TOKYO,
+            scale: 10.0,
+        }
+        .project(TOKYO);
+        assert!(origin.0.abs() < 1e-6 && origin.1.abs() < 1e-6);
+
+        assert!(Projection::Affine {
+            coefficients: [1.0, 2.0, 0.0, 2.0, 4.0, 0.0]
+        }
+        .unproject((0.0, 0.0))
+        .is_none());
+    }
+
+    #[test]
+    fn test_projection_scale() {
+        // North is up, and one meter is `scale` units around the origin
+        let projection = Projection::Equirectangular {
+            origin: TOKYO,
+            scale: 2.0,
+        };
+        let north = GeoCoord {
+            lat: TOKYO.lat + 0.001,
+            lon: TOKYO.lon,
+        };
+        let (x, y) = projection.project(north);
+        assert!(x.abs() < 1e-9 && y < 0.0);
+        assert!((-y / 2.0 - geodesic_distance(TOKYO, north)).abs() < 1e-6);
+
+        // Tokyo to Osaka is about 400 km
+        let d = geodesic_distance(TOKYO, OSAKA);
+        assert!(400000.0 < d && d < 405000.0, "{}", d);
+    }
+
+    #[test]
+    fn test_fit_affine() {
+        let expected = Projection::Affine {
+            coefficients: [1000.0, 10.0, -5.0, 20.0, -1200.0, 7.0],
+        };
+        let control_points = [
+            TOKYO,
+            OSAKA,
+            GeoCoord {
+                lat: 43.06417,
+                lon: 141.34694,
+            },
+            GeoCoord {
+                lat: 33.59,
+                lon: 130.42,
+            },
+        ]
+        .map(|geo| {
+            let (x, y) = expected.project(geo);
+            ControlPoint { geo, x, y }
+        });
+
+        let fitted = Projection::fit_affine(&control_points).unwrap();
+        for geo in [TOKYO, OSAKA] {
+            let (x0, y0) = expected.project(geo);
+            let (x1, y1) = fitted.project(geo);
+            assert!((x0 - x1).abs() < 1e-6 && (y0 - y1).abs() < 1e-6);
+        }
+
+        assert!(Projection::fit_affine(&control_points[..2]).is_none());
+    }
+}
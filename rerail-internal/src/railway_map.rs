@@ -1,15 +1,23 @@
 use wasm_bindgen::prelude::*;
 
 use serde::{Deserialize, Serialize};
-use std::ops::{Index, IndexMut};
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut, Range};
 use tsify::Tsify;
 
 pub use crate::geom::Coord;
 use crate::geom::{
-    compute_station_line_segment, distance_norm_square_point_line_segment,
-    distance_norm_square_points, Rect,
+    average_direction, catmull_rom_direction, catmull_rom_spline, compute_station_line_segment,
+    distance_norm_square_point_line_segment, distance_norm_square_points,
+    nearest_point_on_line_segment, offset_polyline, polygon_contains, railway_direction,
+    simplify_polyline, snap_to_45_degrees, Rect,
 };
+use crate::graph::{articulation_points, connected_components};
+use crate::projection::geodesic_distance;
+pub use crate::projection::{ControlPoint, GeoCoord, Projection};
+use crate::search::{match_name, normalize};
 use crate::sparse_array::{SparseArray, SparseArrayId};
+use crate::spatial_index::SpatialIndex;
 
 #[wasm_bindgen]
 #[derive(Clone, Copy, Serialize, Deserialize)]
@@ -60,22 +68,280 @@ impl Station {
 struct RailwayPoint {
     coord: Coord,
     station: Option<StationIndex>,
+    /// Points with the same weld id are on the same track and always kept at the same position,
+    /// possibly across railways.
+    #[serde(default)]
+    weld: Option<usize>,
+    /// Manually entered official distance (in kilometers) of the station at this point from the
+    /// start of the railway, which overrides the computed distance.
+    #[serde(default)]
+    official_distance: Option<f64>,
 }
 
+/// A company operating railways, which may be shared by many railways.
 #[derive(Serialize, Deserialize)]
+pub struct Operator {
+    name: String,
+}
+
+/// A railway consists of one or more branches, each of which is a polyline formed by a consecutive
+/// range of `points`. A branch other than the first one usually starts at a point shared with
+/// another branch, which makes the railway a tree or graph of track.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Railway {
     name: String,
     color: Color,
     level: u8,
     points: Vec<RailwayPoint>,
+    /// Sorted indices of the first points of the branches other than the first one.
+    /// No segment connects such a point to the preceding point.
+    #[serde(default)]
+    branch_starts: Vec<usize>,
+    /// Whether the first branch forms a ring, connecting its last point back to its first point.
+    #[serde(default)]
+    closed: bool,
+    /// Whether the railway is rendered as a smooth curve through its points.
+    #[serde(default)]
+    smooth: bool,
+    #[serde(default)]
+    metadata: RailwayMetadata,
 }
 
 impl Railway {
     pub fn add_point(&mut self, coord: Coord, station: Option<StationIndex>) {
-        self.points.push(RailwayPoint { coord, station });
+        self.points.push(RailwayPoint {
+            coord,
+            station,
+            weld: None,
+            official_distance: None,
+        });
+    }
+
+    /// Returns the index of the point connected to the `i`-th point by the segment starting at it.
+    /// This is usually `i + 1`, but the segment from the last point of a closed ring leads to 0.
+    fn segment_end(&self, i: usize) -> Option<usize> {
+        if i + 1 < self.points.len() && !self.branch_starts.contains(&(i + 1)) {
+            Some(i + 1)
+        } else if self.is_ring() && i + 1 == self.first_branch_end() {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the index of the point from which the segment ending at the `j`-th point starts.
+    fn segment_start(&self, j: usize) -> Option<usize> {
+        if j > 0 && j < self.points.len() && !self.branch_starts.contains(&j) {
+            Some(j - 1)
+        } else if j == 0 && self.is_ring() {
+            Some(self.first_branch_end() - 1)
+        } else {
+            None
+        }
+    }
+
+    fn has_segment(&self, i: usize) -> bool {
+        self.segment_end(i).is_some()
+    }
+
+    /// Returns the polyline along the segment starting at the `i`-th point as it is rendered:
+    /// the straight segment, or the piece of the spline if the railway is smooth.
+    fn segment_curve(&self, i: usize) -> Vec<Coord> {
+        let j = self.segment_end(i).unwrap();
+        let (c0, c1) = (self.points[i].coord, self.points[j].coord);
+        if !self.smooth {
+            return vec![c0, c1];
+        }
+
+        let to_f64 = |k: usize| {
+            let c = self.points[k].coord;
+            (c.x as f64, c.y as f64)
+        };
+        // The spline is the same in the logical coordinate since it scales with the points
+        catmull_rom_spline(
+            &[to_f64(i), to_f64(j)],
+            self.segment_start(i).map(to_f64),
+            self.segment_end(j).map(to_f64),
+            &[HIT_TEST_CURVE_DIVISIONS],
+        )
+        .into_iter()
+        .map(|(x, y)| Coord::new(x.round() as i32, y.round() as i32))
+        .collect()
+    }
+
+    /// Squared distance from `p` to the segment starting at the `i`-th point, along the curve if
+    /// the railway is smooth.
+    fn distance_norm_square_to_segment(&self, i: usize, p: Coord) -> i128 {
+        self.segment_curve(i)
+            .windows(2)
+            .map(|w| distance_norm_square_point_line_segment(w[0], w[1], p))
+            .min()
+            .unwrap()
+    }
+
+    /// The point on the segment starting at the `i`-th point nearest to `p`, along the curve if the
+    /// railway is smooth.
+    fn nearest_point_on_segment(&self, i: usize, p: Coord) -> Coord {
+        self.segment_curve(i)
+            .windows(2)
+            .map(|w| nearest_point_on_line_segment(w[0], w[1], p))
+            .min_by_key(|&c| distance_norm_square_points(c, p))
+            .unwrap()
+    }
+
+    fn first_branch_end(&self) -> usize {
+        self.branch_starts
+            .first()
+            .copied()
+            .unwrap_or(self.points.len())
+    }
+
+    /// Returns whether the first branch is closed and has enough points to form a ring.
+    fn is_ring(&self) -> bool {
+        self.closed && self.first_branch_end() >= 3
+    }
+
+    fn branch_ranges(&self) -> Vec<Range<usize>> {
+        branch_ranges(&self.branch_starts, self.points.len())
+    }
+
+    /// Returns the point of the preceding branches from which the branch starting at the `s`-th
+    /// point leaves, that is, the first point welded to the start of the branch.
+    fn branch_origin(&self, s: usize) -> Option<usize> {
+        let weld = self.points[s].weld?;
+        (0..s).find(|&j| self.points[j].weld == Some(weld))
+    }
+
+    /// Inserts a point before the `i`-th point.
+    /// If the `i`-th point starts a branch, the new point is appended to the preceding branch.
+    fn insert_point(&mut self, i: usize, point: RailwayPoint) {
+        self.points.insert(i, point);
+        for s in &mut self.branch_starts {
+            if *s >= i {
+                *s += 1;
+            }
+        }
+    }
+
+    /// Removes the `i`-th point. A branch is removed when it becomes empty.
+    fn remove_point(&mut self, i: usize) {
+        self.points.remove(i);
+        for s in &mut self.branch_starts {
+            if *s > i {
+                *s -= 1;
+            }
+        }
+        let n = self.points.len();
+        self.branch_starts.retain(|&s| 0 < s && s < n);
+        self.branch_starts.dedup();
+    }
+
+    /// Extends the first branch with the first branch of `other`, connecting the end of the former
+    /// to the start of the latter. The other branches of `other` are added after the branches.
+    fn append(&mut self, other: Railway) {
+        let end = self.first_branch_end();
+        let other_end = other.first_branch_end();
+        let mut trunk = other.points;
+        let branches = trunk.split_off(other_end);
+
+        let n = trunk.len();
+        self.points.splice(end..end, trunk);
+        for s in &mut self.branch_starts {
+            *s += n;
+        }
+        let offset = self.points.len();
+        self.points.extend(branches);
+        self.branch_starts.extend(
+            other
+                .branch_starts
+                .into_iter()
+                .map(|s| s - other_end + offset),
+        );
+    }
+
+    /// Makes the first branch a ring. If its first and last points are at the same position and do
+    /// not have different stations, they are unified into one point. A railway without points is
+    /// not closed.
+    fn close(&mut self) {
+        if self.closed || self.first_branch_end() == 0 {
+            return;
+        }
+        self.closed = true;
+
+        let last = self.first_branch_end() - 1;
+        if last == 0 {
+            return;
+        }
+        let (first_pt, last_pt) = (&self.points[0], &self.points[last]);
+        if first_pt.coord == last_pt.coord
+            && (first_pt.station.is_none()
+                || last_pt.station.is_none()
+                || first_pt.station == last_pt.station)
+        {
+            let station = first_pt.station.or(last_pt.station);
+            self.points[0].station = station;
+            self.remove_point(last);
+        }
+    }
+
+    /// Opens the ring of the first branch at the `i`-th point, which becomes both the first point
+    /// and (as a copy) the last point of the branch.
+    fn open_at(&mut self, i: usize) {
+        if !self.closed {
+            return;
+        }
+        self.closed = false;
+
+        let end = self.first_branch_end();
+        if end == 0 {
+            return;
+        }
+        self.points[..end].rotate_left(i);
+        self.insert_point(end, self.points[0].clone());
+    }
+
+    fn has_official_distances(&self) -> bool {
+        self.points.iter().any(|pt| pt.official_distance.is_some())
+    }
+
+    /// Reverses the direction of the first branch keeping the set of segments. The first point of a
+    /// ring stays first. The other branches keep their direction, so that they still start at their
+    /// junctions. Official distances are cleared, since they are measured from the old start and
+    /// nothing tells where they would be measured from in the new direction.
+    fn reverse(&mut self) {
+        for pt in &mut self.points {
+            pt.official_distance = None;
+        }
+
+        let end = self.first_branch_end();
+        if self.is_ring() {
+            self.points[1..end].reverse();
+        } else {
+            self.points[..end].reverse();
+        }
+    }
+}
+
+/// Quotes `s` as a CSV field if needed.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
     }
 }
 
+/// Splits `0..n` into the ranges of the branches starting at 0 and `branch_starts`.
+fn branch_ranges(branch_starts: &[usize], n: usize) -> Vec<Range<usize>> {
+    let mut ret = vec![];
+    let mut start = 0;
+    for &s in branch_starts.iter().chain(std::iter::once(&n)) {
+        ret.push(start..s);
+        start = s;
+    }
+    ret
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct BorderPoint {
     coord: Coord,
@@ -128,6 +394,176 @@ impl BorderPoint {
 pub type StationIndex = SparseArrayId<Station>;
 pub type RailwayIndex = SparseArrayId<Railway>;
 pub type BorderPointIndex = SparseArrayId<BorderPoint>;
+pub type OperatorIndex = SparseArrayId<Operator>;
+
+type RailwayPointKey = (RailwayIndex, usize);
+type BorderSegmentKey = (BorderPointIndex, BorderPointIndex);
+
+fn border_segment_key(i: BorderPointIndex, j: BorderPointIndex) -> BorderSegmentKey {
+    if i < j {
+        (i, j)
+    } else {
+        (j, i)
+    }
+}
+
+/// Direction of the railway at the `i`-th point, skipping neighboring points at the same position.
+fn railway_direction_at(railway: &Railway, i: usize) -> Option<(f64, f64)> {
+    let branch = railway
+        .branch_ranges()
+        .into_iter()
+        .find(|r| r.contains(&i))
+        .unwrap();
+    let points = &railway.points[branch.clone()];
+    let i = i - branch.start;
+    let cur = points[i].coord;
+
+    // Neighbors on a ring are searched across the seam
+    let wrap = branch.start == 0 && railway.is_ring();
+    let (before, after) = points.split_at(i);
+    let after = &after[1..];
+    let prev = before
+        .iter()
+        .rev()
+        .chain(after.iter().rev().filter(|_| wrap))
+        .map(|pt| pt.coord)
+        .find(|&c| c != cur);
+    let next = after
+        .iter()
+        .chain(before.iter().filter(|_| wrap))
+        .map(|pt| pt.coord)
+        .find(|&c| c != cur);
+    if railway.smooth {
+        catmull_rom_direction(prev, cur, next)
+    } else {
+        railway_direction(prev, cur, next)
+    }
+}
+
+/// Returns the bounding box of `coords`, or `None` if it is empty.
+fn bounding_box(coords: impl Iterator<Item = Coord>) -> Option<BoundingBox> {
+    coords.fold(None, |acc, c| match acc {
+        None => Some(BoundingBox {
+            left: c.x,
+            top: c.y,
+            right: c.x,
+            bottom: c.y,
+        }),
+        Some(b) => Some(BoundingBox {
+            left: b.left.min(c.x),
+            top: b.top.min(c.y),
+            right: b.right.max(c.x),
+            bottom: b.bottom.max(c.y),
+        }),
+    })
+}
+
+/// Splits sorted segment indices into runs of consecutive segments `[start, end)`.
+fn consecutive_runs(segments: &[usize]) -> Vec<(usize, usize)> {
+    let mut ret: Vec<(usize, usize)> = vec![];
+    for &i in segments {
+        match ret.last_mut() {
+            Some(last) if last.1 == i => last.1 = i + 1,
+            _ => ret.push((i, i + 1)),
+        }
+    }
+    ret
+}
+
+/// Decomposes border segments into chains of points.
+/// A chain continues through points with exactly two incident segments of the same level.
+fn border_chains(
+    segments: &[(BorderPointIndex, BorderPointIndex, u8)],
+) -> Vec<(u8, Vec<BorderPointIndex>)> {
+    let mut adjacent = HashMap::<BorderPointIndex, Vec<usize>>::new();
+    for (e, &(i, j, _)) in segments.iter().enumerate() {
+        adjacent.entry(i).or_default().push(e);
+        adjacent.entry(j).or_default().push(e);
+    }
+    let is_joint = |p: BorderPointIndex| {
+        let adj = &adjacent[&p];
+        adj.len() != 2 || segments[adj[0]].2 != segments[adj[1]].2
+    };
+
+    let mut used = vec![false; segments.len()];
+    let walk = |start: BorderPointIndex, mut e: usize, used: &mut [bool]| {
+        let level = segments[e].2;
+        let mut chain = vec![start];
+        let mut cur = start;
+        loop {
+            used[e] = true;
+            let (i, j, _) = segments[e];
+            cur = if i == cur { j } else { i };
+            chain.push(cur);
+            if is_joint(cur) {
+                break;
+            }
+            let adj = &adjacent[&cur];
+            e = if adj[0] == e { adj[1] } else { adj[0] };
+            if used[e] {
+                break;
+            }
+        }
+        (level, chain)
+    };
+
+    let mut ret = vec![];
+    for e in 0..segments.len() {
+        if used[e] {
+            continue;
+        }
+        let (i, j, _) = segments[e];
+        if is_joint(i) {
+            ret.push(walk(i, e, &mut used));
+        } else if is_joint(j) {
+            ret.push(walk(j, e, &mut used));
+        }
+    }
+    // Remaining segments form cycles
+    for e in 0..segments.len() {
+        if !used[e] {
+            ret.push(walk(segments[e].0, e, &mut used));
+        }
+    }
+    ret
+}
+
+fn group_by_railway(keys: Vec<RailwayPointKey>) -> HashMap<RailwayIndex, Vec<usize>> {
+    let mut ret = HashMap::<RailwayIndex, Vec<usize>>::new();
+    for (rail_id, i) in keys {
+        ret.entry(rail_id).or_default().push(i);
+    }
+    ret
+}
+
+fn translate(coord: Coord, (dx, dy): (i32, i32)) -> Coord {
+    Coord::new(coord.x.saturating_add(dx), coord.y.saturating_add(dy))
+}
+
+/// The pair of the weld ids of the endpoints of the segment from the `i`-th point to the `j`-th
+/// point in the ascending order, which identifies the track shared by segments, and whether the
+/// segment runs in the ascending order.
+fn corridor_key(railway: &Railway, i: usize, j: usize) -> Option<((usize, usize), bool)> {
+    let (wi, wj) = (railway.points[i].weld?, railway.points[j].weld?);
+    (wi != wj).then_some(((wi.min(wj), wi.max(wj)), wi < wj))
+}
+
+/// Spatial indices over the geometry of the map.
+///
+/// A railway segment `(rail_id, i)` connects the `i`-th and `(i + 1)`-th points of the railway,
+/// and a station point `(rail_id, i)` is the `i`-th point of the railway which has a station.
+#[derive(Default)]
+struct GeometryIndex {
+    railway_segments: SpatialIndex<RailwayPointKey>,
+    station_points: SpatialIndex<RailwayPointKey>,
+    border_points: SpatialIndex<BorderPointIndex>,
+    border_segments: SpatialIndex<BorderSegmentKey>,
+    num_railway_points: HashMap<RailwayIndex, usize>,
+    /// Points with each weld id
+    welds: HashMap<usize, Vec<RailwayPointKey>>,
+    /// Weld ids of the points of each railway
+    railway_welds: HashMap<RailwayIndex, Vec<usize>>,
+}
 
 #[wasm_bindgen]
 #[derive(Serialize, Deserialize)]
@@ -135,27 +571,43 @@ pub struct RerailMap {
     stations: SparseArray<Station>,
     railways: SparseArray<Railway>,
     border_points: SparseArray<BorderPoint>,
+    #[serde(default = "SparseArray::new")]
+    operators: SparseArray<Operator>,
     railway_unique_id_last: usize,
+    #[serde(default)]
+    weld_id_last: usize,
+    /// How the logical coordinate is related to the earth, if known.
+    #[serde(default)]
+    projection: Option<Projection>,
+    #[serde(skip)]
+    geometry_index: GeometryIndex,
 }
 
 #[wasm_bindgen(getter_with_clone)]
 #[derive(Clone)]
 pub struct StationRenderingInfo {
     pub name: String,
-    pub x: i32,
-    pub y: i32,
+    pub x: f64,
+    pub y: f64,
 }
 
+/// Polylines to be rendered, grouped by their styles.
+///
+/// The `k`-th group consists of `rail_polylines_num[k]` consecutive polylines drawn with
+/// `rail_colors[k]`, `rail_width[k]` and `rail_style[k]`. The `l`-th polyline consists of
+/// `polyline_points_num[l]` points starting from index `polyline_start[l]` of `rail_points_x` / `rail_points_y`.
 #[wasm_bindgen(getter_with_clone)]
 pub struct RenderingInfo {
     pub rail_colors: Vec<Color>,
     pub rail_width: Vec<i32>,
     pub rail_style: Vec<i32>,
-    pub rail_points_num: Box<[i32]>,
-    pub rail_points_x: Box<[i32]>,
-    pub rail_points_y: Box<[i32]>,
-    pub marker_points_x: Vec<i32>,
-    pub marker_points_y: Vec<i32>,
+    pub rail_polylines_num: Box<[i32]>,
+    pub polyline_start: Box<[i32]>,
+    pub polyline_points_num: Box<[i32]>,
+    pub rail_points_x: Box<[f64]>,
+    pub rail_points_y: Box<[f64]>,
+    pub marker_points_x: Vec<f64>,
+    pub marker_points_y: Vec<f64>,
     pub stations: Vec<StationRenderingInfo>,
 }
 
@@ -174,7 +626,8 @@ pub struct ViewportSpec {
     top_y: i32,
     width: i32,
     height: i32,
-    zoom: i32,
+    /// Size of a physical pixel in the logical coordinate, which may be fractional
+    zoom: f64,
 }
 
 #[derive(Tsify, Serialize, Deserialize)]
@@ -203,6 +656,26 @@ pub struct ExtraBorderSegment {
     level: u8,
 }
 
+/// Railway points, stations and border points selected together.
+/// All the points linked to a selected station are also selected.
+#[derive(Clone, Default, Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct Selection {
+    #[serde(rename = "railwayPoints")]
+    railway_points: Vec<(RailwayIndex, usize)>,
+    stations: Vec<StationIndex>,
+    #[serde(rename = "borderPoints")]
+    border_points: Vec<BorderPointIndex>,
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct TemporaryMovingSelection {
+    selection: Selection,
+    /// Displacement of the selection in physical pixels
+    offset: PhysicalCoord,
+}
+
 #[derive(Tsify, Serialize, Deserialize)]
 #[tsify(into_wasm_abi, from_wasm_abi)]
 pub struct RenderingOptions {
@@ -221,6 +694,15 @@ pub struct RenderingOptions {
     #[tsify(optional)]
     #[serde(rename = "extraBorderSegment")]
     extra_border_segment: Option<ExtraBorderSegment>,
+    #[tsify(optional)]
+    #[serde(rename = "temporaryMovingSelection")]
+    temporary_moving_selection: Option<TemporaryMovingSelection>,
+    #[tsify(optional)]
+    #[serde(rename = "highlightedRoute")]
+    highlighted_route: Option<Route>,
+    #[tsify(optional)]
+    #[serde(rename = "railwayFilter")]
+    railway_filter: Option<RailwayFilter>,
 }
 
 #[derive(Tsify, Serialize, Deserialize)]
@@ -236,25 +718,121 @@ pub struct RailwayInfo {
     name: String,
     level: u8,
     color: u32,
+    #[tsify(optional)]
+    #[serde(default)]
+    metadata: RailwayMetadata,
 }
 
-#[derive(Tsify, Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Tsify, Serialize, Deserialize)]
 #[tsify(into_wasm_abi, from_wasm_abi)]
-pub struct PhysicalCoord {
-    x: i32,
-    y: i32,
+pub enum Electrification {
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "dc")]
+    Dc { voltage: u32 },
+    #[serde(rename = "ac")]
+    Ac { voltage: u32, frequency: u32 },
 }
 
-impl PhysicalCoord {
-    fn as_coord(&self) -> Coord {
-        Coord {
-            x: self.x,
-            y: self.y,
-        }
+/// Descriptive attributes of a railway, which do not affect how it is edited. Unknown attributes
+/// are left unset.
+#[derive(Clone, Default, Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct RailwayMetadata {
+    #[tsify(optional)]
+    operator: Option<OperatorIndex>,
+    /// Line code or symbol, such as "JY"
+    #[tsify(optional)]
+    #[serde(rename = "lineCode")]
+    line_code: Option<String>,
+    #[tsify(optional)]
+    #[serde(rename = "alternativeNames", default)]
+    alternative_names: Vec<String>,
+    /// Track gauge in millimeters
+    #[tsify(optional)]
+    gauge: Option<u32>,
+    #[tsify(optional)]
+    electrification: Option<Electrification>,
+    #[tsify(optional)]
+    #[serde(rename = "numTracks")]
+    num_tracks: Option<u8>,
+    /// Custom attributes as pairs of a key and a value
+    #[tsify(optional)]
+    #[serde(default)]
+    attributes: Vec<(String, String)>,
+}
+
+/// Conditions on the metadata of the railways to be rendered. A railway is rendered only if it
+/// satisfies all the conditions given.
+#[derive(Default, Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct RailwayFilter {
+    /// Railways operated by one of them
+    #[tsify(optional)]
+    operators: Option<Vec<OperatorIndex>>,
+    /// Railways with one of these gauges
+    #[tsify(optional)]
+    gauges: Option<Vec<u32>>,
+    /// Railways known to be electrified (or not)
+    #[tsify(optional)]
+    electrified: Option<bool>,
+    /// Railways known to have at least this number of tracks
+    #[tsify(optional)]
+    #[serde(rename = "minTracks")]
+    min_tracks: Option<u8>,
+    /// Railways having all of these attributes
+    #[tsify(optional)]
+    #[serde(default)]
+    attributes: Vec<(String, String)>,
+}
+
+impl RailwayFilter {
+    fn matches(&self, metadata: &RailwayMetadata) -> bool {
+        let operator = self
+            .operators
+            .as_ref()
+            .is_none_or(|operators| metadata.operator.is_some_and(|op| operators.contains(&op)));
+        let gauge = self
+            .gauges
+            .as_ref()
+            .is_none_or(|gauges| metadata.gauge.is_some_and(|g| gauges.contains(&g)));
+        let electrified = self.electrified.is_none_or(|electrified| {
+            metadata
+                .electrification
+                .is_some_and(|e| (e != Electrification::None) == electrified)
+        });
+        let tracks = self
+            .min_tracks
+            .is_none_or(|min_tracks| metadata.num_tracks.is_some_and(|n| n >= min_tracks));
+        let attributes = self
+            .attributes
+            .iter()
+            .all(|attr| metadata.attributes.contains(attr));
+        operator && gauge && electrified && tracks && attributes
     }
 }
 
-fn split_into_x_and_y(points: &[PhysicalCoord]) -> (Vec<i32>, Vec<i32>) {
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct OperatorInfo {
+    id: OperatorIndex,
+    name: String,
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct OperatorList {
+    operators: Vec<OperatorInfo>,
+}
+
+#[derive(Tsify, Clone, Copy, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct PhysicalCoord {
+    x: f64,
+    y: f64,
+}
+
+fn split_into_x_and_y(points: &[PhysicalCoord]) -> (Vec<f64>, Vec<f64>) {
     let mut xs = vec![];
     let mut ys = vec![];
     for pt in points {
@@ -264,17 +842,53 @@ fn split_into_x_and_y(points: &[PhysicalCoord]) -> (Vec<i32>, Vec<i32>) {
     (xs, ys)
 }
 
+#[derive(Default)]
+struct PolylineCollector {
+    colors: Vec<Color>,
+    width: Vec<i32>,
+    style: Vec<i32>,
+    polylines_num: Vec<i32>,
+    polyline_start: Vec<i32>,
+    polyline_points_num: Vec<i32>,
+    points: Vec<PhysicalCoord>,
+    num_pending_polylines: i32,
+}
+
+impl PolylineCollector {
+    fn add_polyline(&mut self, points: &[PhysicalCoord]) {
+        if points.len() < 2 {
+            return;
+        }
+        self.polyline_start.push(self.points.len() as i32);
+        self.polyline_points_num.push(points.len() as i32);
+        self.points.extend(points);
+        self.num_pending_polylines += 1;
+    }
+
+    /// Finishes the group consisting of the polylines added since the last call.
+    /// Empty groups are omitted.
+    fn end_group(&mut self, color: Color, width: i32, style: i32) {
+        if self.num_pending_polylines > 0 {
+            self.colors.push(color);
+            self.width.push(width);
+            self.style.push(style);
+            self.polylines_num.push(self.num_pending_polylines);
+            self.num_pending_polylines = 0;
+        }
+    }
+}
+
 struct Viewport {
     left_x: i32,
     top_y: i32,
-    zoom: i32,
+    zoom: f64,
     bounding_box: Rect,
 }
 
 impl Viewport {
     fn new(spec: ViewportSpec) -> Viewport {
-        let bottom = spec.top_y + spec.height * spec.zoom;
-        let right = spec.left_x + spec.width * spec.zoom;
+        let bottom = (spec.top_y as f64 + spec.height as f64 * spec.zoom).ceil() as i32;
+        let right = (spec.left_x as f64 + spec.width as f64 * spec.zoom).ceil() as i32;
 
         Viewport {
             left_x: spec.left_x,
@@ -288,21 +902,41 @@ impl Viewport {
         self.bounding_box.contains(coord)
     }
 
+    /// Radius in the logical coordinate which covers all points within `max_dist` in the physical coordinate.
+    fn logical_radius(&self, max_dist: f64) -> i32 {
+        ((max_dist * self.zoom).ceil() as i32).saturating_add(1)
+    }
+
+    /// Displacement in the logical coordinate corresponding to `offset` in the physical coordinate.
+    fn logical_offset(&self, offset: PhysicalCoord) -> (i32, i32) {
+        (
+            (offset.x * self.zoom).round() as i32,
+            (offset.y * self.zoom).round() as i32,
+        )
+    }
+
+    /// Squared distance in the logical coordinate corresponding to `dist` in the physical coordinate.
+    fn logical_distance_sq(&self, dist: f64) -> f64 {
+        let d = dist * self.zoom;
+        d * d
+    }
+
     fn crosses_with_line_segment(&self, a: Coord, b: Coord) -> bool {
         self.bounding_box.crosses_with_line_segment(a, b)
     }
 
     fn to_physical_point(&self, coord: Coord) -> PhysicalCoord {
         PhysicalCoord {
-            x: (coord.x - self.left_x) / self.zoom,
-            y: (coord.y - self.top_y) / self.zoom,
+            x: (coord.x as f64 - self.left_x as f64) / self.zoom,
+            y: (coord.y as f64 - self.top_y as f64) / self.zoom,
         }
     }
 
+    #[allow(clippy::wrong_self_convention)]
     fn from_physical_point(&self, coord: PhysicalCoord) -> Coord {
         Coord {
-            x: coord.x * self.zoom + self.left_x,
-            y: coord.y * self.zoom + self.top_y,
+            x: (coord.x * self.zoom + self.left_x as f64).round() as i32,
+            y: (coord.y * self.zoom + self.top_y as f64).round() as i32,
         }
     }
 }
@@ -323,76 +957,355 @@ pub enum BorderPointOrSegment {
     Segment(BorderPointIndex, BorderPointIndex),
 }
 
-#[wasm_bindgen(getter_with_clone)]
-pub struct StationListOnRailway {
-    pub names: Vec<String>,
-    pub distances: Vec<f64>,
+/// An entity hit by `pick`. Entities earlier in the declaration take precedence.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum PickedEntity {
+    /// The station linked to the `usize`-th point of the railway
+    #[serde(rename = "station")]
+    Station(StationIndex, RailwayIndex, usize),
+    #[serde(rename = "railwayPoint")]
+    RailwayPoint(RailwayIndex, usize),
+    #[serde(rename = "borderPoint")]
+    BorderPoint(BorderPointIndex),
+    /// The segment starting at the `usize`-th point of the railway
+    #[serde(rename = "railwaySegment")]
+    RailwaySegment(RailwayIndex, usize),
+    #[serde(rename = "borderSegment")]
+    BorderSegment(BorderPointIndex, BorderPointIndex),
 }
 
-const STATION_THRESHOLD: [[i32; 4]; 4] = [
-    [20, 50, 50, 50],
-    [50, 100, 200, 200],
-    [50, 100, 200, 200],
-    [100, 200, 500, 5000],
-];
-
-const RAILWAY_THRESHOLD: [i32; 4] = [100, 200, 200, 10000];
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct PickHit {
+    entity: PickedEntity,
+    /// Distance from the picked position in physical pixels
+    distance: f64,
+}
 
-#[wasm_bindgen]
-pub struct RerailMapAndRailwayIndex {
-    map: Option<RerailMap>,
-    index: RailwayIndex,
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct PickResult {
+    hits: Vec<PickHit>,
 }
 
-#[wasm_bindgen]
-impl RerailMapAndRailwayIndex {
-    #[wasm_bindgen(js_name = getMap)]
-    pub fn get_map(&mut self) -> RerailMap {
-        self.map.take().unwrap()
-    }
+/// A station which a railway point can be linked to.
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct StationCandidate {
+    station: StationIndex,
+    name: String,
+    /// Position of the point of the station nearest to the query in the logical coordinate
+    x: i32,
+    y: i32,
+    /// Distance from the query in physical pixels
+    distance: f64,
+}
 
-    #[wasm_bindgen(js_name = getRailwayIndex)]
-    pub fn get_railway_index(&self) -> RailwayIndex {
-        self.index
-    }
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct StationCandidates {
+    candidates: Vec<StationCandidate>,
 }
 
-#[wasm_bindgen]
-impl RerailMap {
-    pub fn new() -> RerailMap {
-        RerailMap {
-            stations: SparseArray::new(),
-            railways: SparseArray::new(),
-            border_points: SparseArray::new(),
-            railway_unique_id_last: 0,
-        }
-    }
+/// A segment sharing the track with segments of other railways.
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct SharedSegment {
+    /// The segment starts at the `index`-th point of the railway
+    index: usize,
+    /// Railways with segments on the same track
+    railways: Vec<RailwayIndex>,
+}
 
-    pub(crate) fn add_station(&mut self, station: Station) -> StationIndex {
-        self.stations.push(station)
-    }
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct SharedSegments {
+    segments: Vec<SharedSegment>,
+}
 
-    pub(crate) fn new_railway(&mut self, name: String, color: Color, level: u8) -> RailwayIndex {
-        let railway = Railway {
-            name,
-            color,
-            level,
-            points: vec![],
-        };
-        self.railways.push(railway)
-    }
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct SnapOptions {
+    /// Maximum distance to the snap targets in physical pixels
+    radius: f64,
+    /// The railway being edited, whose segments are not snap targets
+    #[tsify(optional)]
+    #[serde(rename = "railId")]
+    rail_id: Option<usize>,
+    /// The point of `rail_id` being moved or inserted
+    #[tsify(optional)]
+    #[serde(rename = "editingIndex")]
+    editing_index: Option<IndexOnRailway>,
+    /// The border point from which a new border segment is extended
+    #[tsify(optional)]
+    #[serde(rename = "borderPoint")]
+    border_point: Option<BorderPointIndex>,
+    /// Spacing of the grid in the logical coordinate
+    #[tsify(optional)]
+    grid: Option<i32>,
+    /// Whether to snap to the directions of multiples of 45 degrees from the previous point
+    #[tsify(optional)]
+    #[serde(default)]
+    angle: bool,
+}
 
-    pub(crate) fn add_border_point(&mut self, border_point: BorderPoint) -> BorderPointIndex {
-        self.border_points.push(border_point)
-    }
+#[derive(Clone, Copy, Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum SnapTarget {
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "station")]
+    Station(StationIndex),
+    #[serde(rename = "railwayPoint")]
+    RailwayPoint(RailwayIndex, usize),
+    #[serde(rename = "borderPoint")]
+    BorderPoint(BorderPointIndex),
+    /// The segment starting at the `usize`-th point of the railway
+    #[serde(rename = "railwaySegment")]
+    RailwaySegment(RailwayIndex, usize),
+    #[serde(rename = "angle")]
+    Angle,
+    #[serde(rename = "grid")]
+    Grid,
+}
 
-    pub fn load(data: &[u8]) -> RerailMap {
-        let mut data = data;
-        assert!(data.len() >= 2);
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct SnapResult {
+    /// Snapped position in the logical coordinate
+    x: i32,
+    y: i32,
+    target: SnapTarget,
+}
+
+#[derive(Clone, Copy, Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum SearchResultKind {
+    #[serde(rename = "station")]
+    Station,
+    #[serde(rename = "railway")]
+    Railway,
+}
+
+/// Bounding box in the logical coordinate. Both ends are inclusive.
+#[derive(Clone, Copy, Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct BoundingBox {
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32,
+}
+
+/// A station or railway found by `search`, with the bounding box of its points.
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct SearchResult {
+    kind: SearchResultKind,
+    id: usize,
+    name: String,
+    level: u8,
+    bounds: BoundingBox,
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct SearchResults {
+    results: Vec<SearchResult>,
+}
+
+/// A part of a route riding a single railway.
+#[derive(Clone, Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct RouteLeg {
+    railway: RailwayIndex,
+    /// Stations passed in order, including the ones where the leg starts and ends
+    stations: Vec<StationIndex>,
+    /// Points of the railway passed in order
+    points: Vec<usize>,
+    /// Length of the leg in kilometers
+    distance: f64,
+}
+
+#[derive(Clone, Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct Route {
+    legs: Vec<RouteLeg>,
+    /// Total length of the legs in kilometers
+    distance: f64,
+    /// `distance` plus the penalties of the transfers, which the route minimizes
+    cost: f64,
+}
+
+/// Railways connected to each other through shared stations, with those stations.
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct NetworkComponent {
+    railways: Vec<RailwayIndex>,
+    stations: Vec<StationIndex>,
+}
+
+/// Stations which may have to be merged into an interchange.
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct UnlinkedStationPair {
+    a: StationIndex,
+    b: StationIndex,
+    /// Distance between the nearest points of the stations in the logical coordinate
+    distance: f64,
+    #[serde(rename = "sameName")]
+    same_name: bool,
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct ConnectivityReport {
+    /// Sorted by the number of railways in descending order
+    components: Vec<NetworkComponent>,
+    /// Railways sharing no station with other railways
+    #[serde(rename = "isolatedRailways")]
+    isolated_railways: Vec<RailwayIndex>,
+    /// Stations without which some railways through them are no longer connected
+    #[serde(rename = "articulationStations")]
+    articulation_stations: Vec<StationIndex>,
+    #[serde(rename = "unlinkedStationPairs")]
+    unlinked_station_pairs: Vec<UnlinkedStationPair>,
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct ControlPoints {
+    points: Vec<ControlPoint>,
+}
+
+/// Stations on a railway listed branch by branch, with their distances (in kilometers) from the
+/// start of the railway. The stations of the `k`-th branch start at `branch_starts[k]` in `names`
+/// and `distances`.
+#[wasm_bindgen(getter_with_clone)]
+pub struct StationListOnRailway {
+    pub names: Vec<String>,
+    pub distances: Vec<f64>,
+    pub branch_starts: Vec<usize>,
+    /// Indices of the points of the entries on the railway
+    pub points: Vec<usize>,
+    /// Indices of the entries whose distances are the official ones rather than computed
+    pub official: Vec<usize>,
+}
+
+const STATION_THRESHOLD: [[i32; 4]; 4] = [
+    [20, 50, 50, 50],
+    [50, 100, 200, 200],
+    [50, 100, 200, 200],
+    [100, 200, 500, 5000],
+];
+
+const RAILWAY_THRESHOLD: [i32; 4] = [100, 200, 200, 10000];
+
+// Maximum deviation (in physical pixels) allowed when simplifying polylines for rendering
+const SIMPLIFICATION_TOLERANCE: f64 = 0.5;
+
+// Length (in physical pixels) of the pieces approximating smooth railways
+const SMOOTH_CURVE_STEP: f64 = 4.0;
+
+// Maximum number of pieces into which a segment of a smooth railway is divided
+const MAX_SMOOTH_CURVE_DIVISIONS: usize = 64;
+
+// Number of pieces approximating a segment of a smooth railway for hit-testing
+const HIT_TEST_CURVE_DIVISIONS: usize = 16;
+
+// Width (in physical pixels) of the lines of railways
+const RAILWAY_WIDTH: i32 = 1;
+
+// Radius (in physical pixels) around a station within which the railways through it determine the
+// orientation of its ticks
+const STATION_DIRECTION_RADIUS: f64 = 50.0;
+
+// Gap (in physical pixels) between the lines of railways drawn side by side on a shared segment
+const PARALLEL_RAILWAY_GAP: f64 = 2.0;
+
+#[wasm_bindgen]
+pub struct RerailMapAndOperatorIndex {
+    map: Option<RerailMap>,
+    index: OperatorIndex,
+}
+
+#[wasm_bindgen]
+impl RerailMapAndOperatorIndex {
+    #[wasm_bindgen(js_name = getMap)]
+    pub fn get_map(&mut self) -> RerailMap {
+        self.map.take().unwrap()
+    }
+
+    #[wasm_bindgen(js_name = getOperatorIndex)]
+    pub fn get_operator_index(&self) -> OperatorIndex {
+        self.index
+    }
+}
+
+#[wasm_bindgen]
+pub struct RerailMapAndRailwayIndex {
+    map: Option<RerailMap>,
+    index: RailwayIndex,
+}
+
+#[wasm_bindgen]
+impl RerailMapAndRailwayIndex {
+    #[wasm_bindgen(js_name = getMap)]
+    pub fn get_map(&mut self) -> RerailMap {
+        self.map.take().unwrap()
+    }
+
+    #[wasm_bindgen(js_name = getRailwayIndex)]
+    pub fn get_railway_index(&self) -> RailwayIndex {
+        self.index
+    }
+}
+
+#[wasm_bindgen]
+impl RerailMap {
+    pub fn new() -> RerailMap {
+        RerailMap {
+            stations: SparseArray::new(),
+            railways: SparseArray::new(),
+            border_points: SparseArray::new(),
+            operators: SparseArray::new(),
+            railway_unique_id_last: 0,
+            weld_id_last: 0,
+            projection: None,
+            geometry_index: GeometryIndex::default(),
+        }
+    }
+
+    pub(crate) fn add_station(&mut self, station: Station) -> StationIndex {
+        self.stations.push(station)
+    }
+
+    pub(crate) fn new_railway(&mut self, name: String, color: Color, level: u8) -> RailwayIndex {
+        let railway = Railway {
+            name,
+            color,
+            level,
+            points: vec![],
+            branch_starts: vec![],
+            closed: false,
+            smooth: false,
+            metadata: RailwayMetadata::default(),
+        };
+        self.railways.push(railway)
+    }
+
+    pub(crate) fn add_border_point(&mut self, border_point: BorderPoint) -> BorderPointIndex {
+        self.border_points.push(border_point)
+    }
+
+    pub fn load(data: &[u8]) -> RerailMap {
+        let mut data = data;
+        assert!(data.len() >= 2);
 
-        if data[0] == 'R' as u8 && data[1] == 'M' as u8 {
+        if data[0] == b'R' && data[1] == b'M' {
             crate::loader::load_legacy_railmap_file(&mut data).unwrap()
-        } else if data[0] == 'R' as u8 && data[1] == 'L' as u8 {
+        } else if data[0] == b'R' && data[1] == b'L' {
             RerailMap::load_new_format(data)
         } else {
             panic!();
@@ -401,14 +1314,17 @@ impl RerailMap {
 
     fn load_new_format(data: &[u8]) -> RerailMap {
         let r = flexbuffers::Reader::get_root(data).unwrap();
-        RerailMap::deserialize(r).unwrap()
+        let mut map = RerailMap::deserialize(r).unwrap();
+        map.weld_branch_junctions();
+        map.rebuild_geometry_index();
+        map
     }
 
     pub fn save(&self) -> Box<[u8]> {
         let mut serializer = flexbuffers::FlexbufferSerializer::new();
         self.serialize(&mut serializer).unwrap();
 
-        let mut ret = vec!['R' as u8, 'L' as u8];
+        let mut ret = vec![b'R', b'L'];
         ret.extend(serializer.view());
         ret.into_boxed_slice()
     }
@@ -422,13 +1338,16 @@ impl RerailMap {
         y: i32,
     ) -> RerailMap {
         let railway = &mut self.railways[railway_id];
-        railway.points.insert(
+        railway.insert_point(
             i,
             RailwayPoint {
                 coord: Coord::new(x, y),
                 station: None,
+                weld: None,
+                official_distance: None,
             },
         );
+        self.reindex_railway(railway_id);
         self
     }
 
@@ -440,14 +1359,14 @@ impl RerailMap {
         x: i32,
         y: i32,
     ) -> RerailMap {
-        let railway = &mut self.railways[railway_id];
-        railway.points[i].coord = Coord::new(x, y);
+        self.move_welded_point(railway_id, i, Coord::new(x, y));
         self
     }
 
     #[wasm_bindgen(js_name = moveBorderPoint)]
     pub fn move_border_point(mut self, id: BorderPointIndex, x: i32, y: i32) -> RerailMap {
         self.border_points[id].coord = Coord::new(x, y);
+        self.reindex_border_point(id);
         self
     }
 
@@ -468,6 +1387,9 @@ impl RerailMap {
         self.border_points[k].add_neighbor(i, level);
         self.border_points[j].add_neighbor(k, level);
         self.border_points[k].add_neighbor(j, level);
+
+        self.unindex_border_segment(i, j);
+        self.reindex_border_point(k);
         self
     }
 
@@ -482,6 +1404,7 @@ impl RerailMap {
         let j = self.border_points.push(BorderPoint::new(Coord { x, y }));
         self.border_points[i].add_neighbor(j, level);
         self.border_points[j].add_neighbor(i, level);
+        self.reindex_border_point(j);
         self
     }
 
@@ -501,6 +1424,7 @@ impl RerailMap {
         } else {
             self.border_points[i].add_neighbor(j, level);
             self.border_points[j].add_neighbor(i, level);
+            self.reindex_border_point(i);
         }
         self
     }
@@ -509,6 +1433,11 @@ impl RerailMap {
     pub fn remove_border_point(mut self, i: BorderPointIndex) -> RerailMap {
         let n_adj = self.border_points[i].neighbors.len();
         if n_adj < 3 {
+            let neighbors = self.border_points[i]
+                .neighbors
+                .iter()
+                .map(|&(j, _)| j)
+                .collect::<Vec<_>>();
             if n_adj == 1 {
                 let j = self.border_points[i].neighbors[0].0;
                 self.border_points[i].remove_neighbor(j);
@@ -528,6 +1457,12 @@ impl RerailMap {
                 }
             }
             self.border_points.delete(i);
+
+            for j in neighbors {
+                self.unindex_border_segment(i, j);
+                self.reindex_border_point(j);
+            }
+            self.reindex_border_point(i);
         }
 
         self
@@ -538,13 +1473,16 @@ impl RerailMap {
         self.border_points[i].remove_neighbor(j);
         self.border_points[j].remove_neighbor(i);
 
-        if self.border_points[i].neighbors.len() == 0 {
+        if self.border_points[i].neighbors.is_empty() {
             self.border_points.delete(i);
         }
-        if self.border_points[j].neighbors.len() == 0 {
+        if self.border_points[j].neighbors.is_empty() {
             self.border_points.delete(j);
         }
 
+        self.unindex_border_segment(i, j);
+        self.reindex_border_point(i);
+        self.reindex_border_point(j);
         self
     }
 
@@ -552,7 +1490,8 @@ impl RerailMap {
     pub fn remove_railway_point(mut self, railway_id: RailwayIndex, i: usize) -> RerailMap {
         self = self.detach_station_on_railway(railway_id, i);
         let railway = &mut self.railways[railway_id];
-        railway.points.remove(i);
+        railway.remove_point(i);
+        self.reindex_railway(railway_id);
 
         self
     }
@@ -562,62 +1501,141 @@ impl RerailMap {
         let railway = &mut self.railways[railway_id];
         if let Some(station_idx) = railway.points[i].station {
             railway.points[i].station = None;
-            self[station_idx].remove_railway(railway_id);
-            if self[station_idx].railways.is_empty() {
-                self.stations.delete(station_idx);
+            railway.points[i].official_distance = None;
+            // The station may appear more than once on a branching railway
+            let remains = railway
+                .points
+                .iter()
+                .any(|pt| pt.station == Some(station_idx));
+            if !remains {
+                self[station_idx].remove_railway(railway_id);
+                if self[station_idx].railways.is_empty() {
+                    self.stations.delete(station_idx);
+                }
             }
+            self.reindex_railway(railway_id);
         }
 
         self
     }
 
+    /// Stations which the `index`-th point of the railway can be linked to, that is, stations with
+    /// a point within `radius` physical pixels from `point`, sorted by the distance.
+    /// Stations on the same railway are also included, except for the point itself.
+    #[wasm_bindgen(js_name = stationCandidatesForLink)]
+    pub fn station_candidates_for_link(
+        &self,
+        rail_id: RailwayIndex,
+        index: usize,
+        viewport: ViewportSpec,
+        point: PhysicalCoord,
+        radius: f64,
+    ) -> StationCandidates {
+        let viewport = Viewport::new(viewport);
+        let point = viewport.from_physical_point(point);
+        let threshold = viewport.logical_distance_sq(radius);
+        let current_station = self.railways[rail_id].points[index].station;
+
+        let mut nearest: HashMap<StationIndex, (f64, Coord)> = HashMap::new();
+        for (id, j) in self
+            .geometry_index
+            .station_points
+            .query_around(point, viewport.logical_radius(radius))
+        {
+            if id == rail_id && j == index {
+                continue;
+            }
+            let pt = &self.railways[id].points[j];
+            let station_idx = pt.station.unwrap();
+            if Some(station_idx) == current_station {
+                continue;
+            }
+
+            let dist_sq = distance_norm_square_points(pt.coord, point) as f64;
+            if dist_sq > threshold {
+                continue;
+            }
+            let entry = nearest.entry(station_idx).or_insert((dist_sq, pt.coord));
+            if dist_sq < entry.0 {
+                *entry = (dist_sq, pt.coord);
+            }
+        }
+
+        let mut candidates = nearest
+            .into_iter()
+            .map(|(station, (dist_sq, coord))| StationCandidate {
+                station,
+                name: self[station].name.clone(),
+                x: coord.x,
+                y: coord.y,
+                distance: dist_sq.sqrt() / viewport.zoom,
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| {
+            (a.distance, a.station)
+                .partial_cmp(&(b.distance, b.station))
+                .unwrap()
+        });
+
+        StationCandidates { candidates }
+    }
+
+    /// Links the `index`-th point of the railway to the nearest station within `radius` physical
+    /// pixels from `point`. Nothing is changed if the point already has a station or no station is
+    /// in range.
     #[wasm_bindgen(js_name = linkToStation)]
     pub fn link_to_station(
-        mut self,
+        self,
         rail_id: RailwayIndex,
         index: usize,
         viewport: ViewportSpec,
         point: PhysicalCoord,
+        radius: f64,
     ) -> RerailMap {
-        if self.railways[rail_id].points[index].station.is_none() {
-            let viewport = Viewport::new(viewport);
-            let point = point.as_coord();
-
-            let mut nearest_station = None;
-            let mut nearst_distance_sq = 101; // TODO
-
-            for (id, railway) in self.railways.enumerate() {
-                if id == rail_id {
-                    continue;
-                }
-                for j in 0..railway.points.len() {
-                    if !railway.points[j].station.is_some() {
-                        continue;
-                    }
+        if self.railways[rail_id].points[index].station.is_some() {
+            return self;
+        }
 
-                    let dist_sq = distance_norm_square_points(
-                        viewport
-                            .to_physical_point(railway.points[j].coord)
-                            .as_coord(),
-                        point,
-                    );
-                    if dist_sq < nearst_distance_sq {
-                        nearest_station =
-                            Some((railway.points[j].coord, railway.points[j].station.unwrap()));
-                        nearst_distance_sq = dist_sq;
-                    }
-                }
-            }
+        let nearest = self
+            .station_candidates_for_link(rail_id, index, viewport, point, radius)
+            .candidates
+            .into_iter()
+            .next();
+        match nearest {
+            Some(candidate) => self.link_to_station_candidate(rail_id, index, candidate),
+            None => self,
+        }
+    }
 
-            if let Some((coord, station_id)) = nearest_station {
-                self.railways[rail_id].points[index] = RailwayPoint {
-                    coord,
-                    station: Some(station_id),
-                };
-                self[station_id].add_railway(rail_id);
-            }
+    /// Links the `index`-th point of the railway to a station returned by
+    /// `stationCandidatesForLink`, moving the point onto the point of the station nearest to the
+    /// position of the candidate. The candidate may be stale, so nothing is changed if the station
+    /// no longer exists, or if the point already has a station.
+    #[wasm_bindgen(js_name = linkToStationCandidate)]
+    pub fn link_to_station_candidate(
+        mut self,
+        rail_id: RailwayIndex,
+        index: usize,
+        candidate: StationCandidate,
+    ) -> RerailMap {
+        let station_id = candidate.station;
+        if self.railways[rail_id].points[index].station.is_some()
+            || self.stations.get(station_id).is_none()
+        {
+            return self;
         }
+        let target = Coord::new(candidate.x, candidate.y);
+        let coord = match self
+            .station_coords(station_id)
+            .min_by_key(|&c| distance_norm_square_points(c, target))
+        {
+            Some(coord) => coord,
+            None => return self,
+        };
 
+        self.railways[rail_id].points[index].station = Some(station_id);
+        self[station_id].add_railway(rail_id);
+        self.move_welded_point(rail_id, index, coord);
         self
     }
 
@@ -628,15 +1646,22 @@ impl RerailMap {
         let mut rail_names = vec![];
         let mut rail_ids = vec![];
 
+        let visible_segments = group_by_railway(
+            self.geometry_index
+                .railway_segments
+                .query(&viewport.bounding_box),
+        );
+
         for (id, railway) in self.railways.enumerate() {
-            if viewport.zoom > RAILWAY_THRESHOLD[railway.level as usize] {
+            if viewport.zoom > RAILWAY_THRESHOLD[railway.level as usize] as f64 {
                 continue;
             }
 
             let mut is_displayed = false;
-            for j in 1..railway.points.len() {
+            for &j in visible_segments.get(&id).into_iter().flatten() {
+                let k = railway.segment_end(j).unwrap();
                 if viewport
-                    .crosses_with_line_segment(railway.points[j - 1].coord, railway.points[j].coord)
+                    .crosses_with_line_segment(railway.points[j].coord, railway.points[k].coord)
                 {
                     is_displayed = true;
                     break;
@@ -658,197 +1683,521 @@ impl RerailMap {
     pub fn render(&self, viewport: ViewportSpec, opts: RenderingOptions) -> RenderingInfo {
         let viewport = Viewport::new(viewport);
 
-        let mut rail_colors = vec![];
-        let mut rail_width = vec![];
-        let mut rail_style = vec![];
-        let mut rail_points_num = vec![];
-        let mut rail_points = vec![];
+        let mut polylines = PolylineCollector::default();
         let mut stations = vec![];
 
         let mut marker_points = vec![];
 
-        let mut selected_railway_points = vec![];
+        // Railways and border points displaced by the selection being moved
+        let mut moved_railways = HashMap::<RailwayIndex, Railway>::new();
+        let mut moved_border_points = HashMap::<BorderPointIndex, Coord>::new();
+        if let Some(mv) = &opts.temporary_moving_selection {
+            let offset = viewport.logical_offset(mv.offset);
+            for (rail_id, i) in self.with_welded_points(&mv.selection.railway_points) {
+                if let Some(railway) = self.railways.get(rail_id) {
+                    if i < railway.points.len() {
+                        let railway = moved_railways
+                            .entry(rail_id)
+                            .or_insert_with(|| railway.clone());
+                        let coord = translate(railway.points[i].coord, offset);
+                        railway.points[i].coord = coord;
+                        if viewport.contains(coord) {
+                            marker_points.push(viewport.to_physical_point(coord));
+                        }
+                    }
+                }
+            }
+            for &i in &mv.selection.border_points {
+                if let Some(pt) = self.border_points.get(i) {
+                    let coord = translate(pt.coord, offset);
+                    moved_border_points.insert(i, coord);
+                    if viewport.contains(coord) {
+                        marker_points.push(viewport.to_physical_point(coord));
+                    }
+                }
+            }
+        }
+
+        let mut selected_railway = None;
         if let Some(id) = opts.selected_rail_id {
             let id = RailwayIndex::from_usize(id);
-            if let Some(selected_railway) = self.railways.get(id) {
-                selected_railway_points = selected_railway.points.clone();
+            if let Some(railway) = moved_railways.get(&id).or(self.railways.get(id)) {
+                let mut railway = railway.clone();
 
                 if let Some(temporary_moving_point) = &opts.temporary_moving_point {
                     let mouse_coord =
                         viewport.from_physical_point(temporary_moving_point.point_after_move);
                     if temporary_moving_point.index.inserting {
-                        selected_railway_points.insert(
+                        railway.insert_point(
                             temporary_moving_point.index.index,
                             RailwayPoint {
                                 coord: mouse_coord,
                                 station: None,
+                                weld: None,
+                                official_distance: None,
                             },
                         );
                     } else {
-                        selected_railway_points[temporary_moving_point.index.index].coord =
-                            mouse_coord;
+                        // Points welded to the moving point follow it
+                        let key = (id, temporary_moving_point.index.index);
+                        for (r, k) in self.with_welded_points(&[key]) {
+                            if r == id {
+                                railway.points[k].coord = mouse_coord;
+                            } else if let Some(other) = self.railways.get(r) {
+                                moved_railways
+                                    .entry(r)
+                                    .or_insert_with(|| other.clone())
+                                    .points[k]
+                                    .coord = mouse_coord;
+                            }
+                        }
                     }
                 }
+                selected_railway = Some(railway);
             }
         }
+        let railway_of = |id: RailwayIndex| match &selected_railway {
+            Some(railway) if Some(id.as_usize()) == opts.selected_rail_id => railway,
+            _ => moved_railways.get(&id).unwrap_or(&self.railways[id]),
+        };
+        // The index knows only the positions before the temporary moves
+        let is_moving = |id: RailwayIndex| {
+            Some(id.as_usize()) == opts.selected_rail_id || moved_railways.contains_key(&id)
+        };
+        // Railways excluded by the filter, except for the one being edited
+        let is_filtered_out = |id: RailwayIndex, railway: &Railway| {
+            Some(id.as_usize()) != opts.selected_rail_id
+                && opts
+                    .railway_filter
+                    .as_ref()
+                    .is_some_and(|filter| !filter.matches(&railway.metadata))
+        };
 
-        for pt in &selected_railway_points {
+        for pt in selected_railway.iter().flat_map(|railway| &railway.points) {
             let coord = pt.coord;
             if viewport.contains(coord) {
                 marker_points.push(viewport.to_physical_point(coord))
             }
         }
 
+        let visible_segments = group_by_railway(
+            self.geometry_index
+                .railway_segments
+                .query(&viewport.bounding_box),
+        );
+        let visible_station_points = group_by_railway(
+            self.geometry_index
+                .station_points
+                .query(&viewport.bounding_box),
+        );
+
+        // Segments of each railway to be rendered
+        let mut rendered_segments = vec![];
         for (id, railway) in self.railways.enumerate() {
-            if viewport.zoom > RAILWAY_THRESHOLD[railway.level as usize] {
+            if viewport.zoom > RAILWAY_THRESHOLD[railway.level as usize] as f64
+                || is_filtered_out(id, railway)
+            {
                 continue;
             }
 
-            let railway_points = if Some(id.as_usize()) == opts.selected_rail_id {
-                &selected_railway_points
+            let rendered_railway = railway_of(id);
+            let railway_points = &rendered_railway.points;
+            let segments = if is_moving(id) {
+                (0..railway_points.len())
+                    .filter(|&i| rendered_railway.has_segment(i))
+                    .collect()
             } else {
-                &railway.points
+                visible_segments.get(&id).cloned().unwrap_or_default()
             };
 
-            let mut num = 0;
-            for i in 1..railway_points.len() {
-                if viewport
-                    .crosses_with_line_segment(railway_points[i - 1].coord, railway_points[i].coord)
-                {
-                    num += 2;
+            let segments = segments
+                .into_iter()
+                .filter(|&i| {
+                    viewport.crosses_with_line_segment(
+                        railway_points[i].coord,
+                        railway_points[rendered_railway.segment_end(i).unwrap()].coord,
+                    )
+                })
+                .collect::<Vec<_>>();
+            rendered_segments.push((id, segments));
+        }
 
-                    rail_points.push(viewport.to_physical_point(railway_points[i - 1].coord));
-                    rail_points.push(viewport.to_physical_point(railway_points[i].coord));
+        // Railways running on segments welded to each other are drawn side by side in the order of
+        // their indices. The sides are taken in the direction of the first railway, so that each
+        // railway stays on the same side all along the shared corridor wherever it turns.
+        let mut corridors = HashMap::<(usize, usize), Vec<(RailwayIndex, bool)>>::new();
+        for (id, segments) in &rendered_segments {
+            let railway = railway_of(*id);
+            for &i in segments {
+                if let Some((key, forward)) =
+                    corridor_key(railway, i, railway.segment_end(i).unwrap())
+                {
+                    corridors.entry(key).or_default().push((*id, forward));
+                }
+            }
+        }
+        for ids in corridors.values_mut() {
+            ids.sort_by_key(|&(id, _)| id);
+            ids.dedup_by_key(|&mut (id, _)| id);
+        }
+        let spacing = RAILWAY_WIDTH as f64 + PARALLEL_RAILWAY_GAP;
+        // Half of the width (in physical pixels) of the railways side by side around each point
+        let mut corridor_half_width = HashMap::<Coord, f64>::new();
+        for (id, segments) in &rendered_segments {
+            let railway = railway_of(*id);
+            for &i in segments {
+                let j = railway.segment_end(i).unwrap();
+                if let Some((key, _)) = corridor_key(railway, i, j) {
+                    let half_width = (corridors[&key].len() - 1) as f64 * spacing * 0.5;
+                    for k in [i, j] {
+                        let w = corridor_half_width
+                            .entry(railway.points[k].coord)
+                            .or_insert(0.0);
+                        *w = w.max(half_width);
+                    }
                 }
             }
+        }
+        // Offset of the railway `id` on the segment from the `i`-th point to the `j`-th point, to
+        // the side of `(-dy, dx)`
+        let segment_offset = |id: RailwayIndex, railway: &Railway, i: usize, j: usize| {
+            let (key, forward) = match corridor_key(railway, i, j) {
+                Some(key) => key,
+                None => return 0.0,
+            };
+            let ids = &corridors[&key];
+            let pos = ids.iter().position(|&(r, _)| r == id).unwrap();
+            let offset = (pos as f64 - (ids.len() - 1) as f64 * 0.5) * spacing;
+            if forward == ids[0].1 {
+                offset
+            } else {
+                -offset
+            }
+        };
+
+        for (id, segments) in rendered_segments {
+            let rendered_railway = railway_of(id);
+            let railway_points = &rendered_railway.points;
+
+            // The selected railway is rendered as is since its points are being edited, and
+            // smooth railways since their curves depend on all the points
+            let simplify = Some(id.as_usize()) != opts.selected_rail_id && !rendered_railway.smooth;
+
+            for (start, end) in consecutive_runs(&segments) {
+                let indices = std::iter::once(start)
+                    .chain((start..end).map(|i| rendered_railway.segment_end(i).unwrap()))
+                    .collect::<Vec<_>>();
+                let coords = indices
+                    .iter()
+                    .map(|&i| railway_points[i].coord)
+                    .collect::<Vec<_>>();
+                let offsets = indices
+                    .windows(2)
+                    .map(|w| segment_offset(id, rendered_railway, w[0], w[1]))
+                    .collect::<Vec<_>>();
+                let retained = if simplify {
+                    // Points where the offset changes are retained so that each segment of the
+                    // simplified polyline has a single offset
+                    let keep = (0..indices.len())
+                        .map(|k| {
+                            railway_points[indices[k]].station.is_some()
+                                || (0 < k && k < offsets.len() && offsets[k - 1] != offsets[k])
+                        })
+                        .collect::<Vec<_>>();
+                    simplify_polyline(&coords, &keep, SIMPLIFICATION_TOLERANCE * viewport.zoom)
+                } else {
+                    (0..coords.len()).collect()
+                };
 
-            if num > 0 {
-                rail_colors.push(railway.color);
-                rail_width.push(1);
-                rail_style.push(0);
-                rail_points_num.push(num);
+                let to_physical = |c: Coord| {
+                    let pt = viewport.to_physical_point(c);
+                    (pt.x, pt.y)
+                };
+                let mut points = retained
+                    .iter()
+                    .map(|&k| to_physical(coords[k]))
+                    .collect::<Vec<_>>();
+                let mut retained_offsets = retained
+                    .windows(2)
+                    .map(|w| offsets[w[0]])
+                    .collect::<Vec<_>>();
+
+                if rendered_railway.smooth {
+                    // The points just outside the run keep the curve the same while scrolling
+                    let control_point =
+                        |i: Option<usize>| i.map(|i| to_physical(railway_points[i].coord));
+                    let before = control_point(rendered_railway.segment_start(start));
+                    let after =
+                        control_point(rendered_railway.segment_end(indices[indices.len() - 1]));
+                    let divisions = points
+                        .windows(2)
+                        .map(|w| {
+                            let len =
+                                ((w[1].0 - w[0].0).powi(2) + (w[1].1 - w[0].1).powi(2)).sqrt();
+                            ((len / SMOOTH_CURVE_STEP).ceil() as usize)
+                                .clamp(1, MAX_SMOOTH_CURVE_DIVISIONS)
+                        })
+                        .collect::<Vec<_>>();
+                    points = catmull_rom_spline(&points, before, after, &divisions);
+                    retained_offsets = retained_offsets
+                        .iter()
+                        .zip(&divisions)
+                        .flat_map(|(&o, &d)| std::iter::repeat_n(o, d))
+                        .collect();
+                }
+                polylines.add_polyline(
+                    &offset_polyline(&points, &retained_offsets)
+                        .into_iter()
+                        .map(|(x, y)| PhysicalCoord { x, y })
+                        .collect::<Vec<_>>(),
+                );
             }
+            polylines.end_group(self.railways[id].color, RAILWAY_WIDTH, 0);
         }
 
-        let mut station_points = vec![];
-        let mut station_rendered = std::collections::BTreeSet::<StationIndex>::new();
+        if let Some(route) = &opts.highlighted_route {
+            for leg in &route.legs {
+                if let Some(railway) = self.railways.get(leg.railway) {
+                    let points = leg
+                        .points
+                        .iter()
+                        .filter_map(|&i| railway.points.get(i))
+                        .map(|pt| viewport.to_physical_point(pt.coord))
+                        .collect::<Vec<_>>();
+                    polylines.add_polyline(&points);
+                }
+            }
+            polylines.end_group(
+                Color {
+                    r: 255,
+                    g: 128,
+                    b: 0,
+                },
+                3,
+                0,
+            );
+        }
 
+        let mut visible_stations = vec![];
         for (id, railway) in self.railways.enumerate() {
             let rail_level = railway.level as usize;
-            if viewport.zoom > RAILWAY_THRESHOLD[rail_level] {
+            if viewport.zoom > RAILWAY_THRESHOLD[rail_level] as f64 || is_filtered_out(id, railway)
+            {
                 continue;
             }
-            let railway_points = if Some(id.as_usize()) == opts.selected_rail_id {
-                &selected_railway_points
+            let railway_points = &railway_of(id).points;
+            let indices = if is_moving(id) {
+                (0..railway_points.len()).collect()
             } else {
-                &railway.points
+                visible_station_points.get(&id).cloned().unwrap_or_default()
             };
-            for i in 0..railway_points.len() {
+            for i in indices {
                 if let Some(station_idx) = railway_points[i].station {
-                    if !railway_points[i].station.is_some() {
-                        continue;
-                    }
                     if !viewport.contains(railway_points[i].coord) {
                         continue;
                     }
                     let station_level = self[station_idx].level as usize;
-                    if viewport.zoom > STATION_THRESHOLD[rail_level][station_level] {
+                    if viewport.zoom > STATION_THRESHOLD[rail_level][station_level] as f64 {
                         continue;
                     }
+                    visible_stations.push((railway_points[i].coord, station_idx));
+                }
+            }
+        }
 
-                    let prev = if i == 0 {
-                        None
-                    } else {
-                        Some(railway_points[i - 1].coord)
-                    };
-                    let cur = railway_points[i].coord;
-                    let next = if i + 1 == railway_points.len() {
-                        None
-                    } else {
-                        Some(railway_points[i + 1].coord)
-                    };
-
-                    let (c0, c1) = compute_station_line_segment(prev, cur, next, 200);
+        // Ticks of a station share the orientation averaged over the railways through it nearby
+        let direction_radius = viewport.logical_radius(STATION_DIRECTION_RADIUS);
+        let mut station_directions = HashMap::<StationIndex, Vec<(f64, f64)>>::new();
+        for &(coord, station_idx) in &visible_stations {
+            if station_directions.contains_key(&station_idx) {
+                continue;
+            }
+            let mut directions = vec![];
+            let mut points = self
+                .geometry_index
+                .station_points
+                .query_around(coord, direction_radius);
+            // The index knows only the positions before the temporary moves
+            points.retain(|&(rail_id, _)| !is_moving(rail_id));
+            for &rail_id in &self[station_idx].railways {
+                if is_moving(rail_id) {
+                    let railway = railway_of(rail_id);
+                    points.extend((0..railway.points.len()).map(|i| (rail_id, i)));
+                }
+            }
+            for (rail_id, i) in points {
+                let railway = railway_of(rail_id);
+                if railway.points.get(i).and_then(|pt| pt.station) == Some(station_idx) {
+                    directions.extend(railway_direction_at(railway, i));
+                }
+            }
+            station_directions.insert(station_idx, directions);
+        }
 
-                    station_points.push(viewport.to_physical_point(c0));
-                    station_points.push(viewport.to_physical_point(c1));
+        let mut station_rendered = std::collections::BTreeSet::<StationIndex>::new();
+        let mut ticks_rendered = std::collections::BTreeSet::<(StationIndex, Coord)>::new();
+        for (coord, station_idx) in visible_stations {
+            let direction = average_direction(&station_directions[&station_idx]);
+            let (c0, c1) = compute_station_line_segment(coord, direction, 200);
+            let (p0, p1) = (
+                viewport.to_physical_point(c0),
+                viewport.to_physical_point(c1),
+            );
 
-                    if station_rendered.contains(&station_idx) {
-                        continue;
-                    }
-                    station_rendered.insert(station_idx);
+            // The tick is repeated across the railways drawn side by side at the station, once
+            // for all of them
+            let half_width = corridor_half_width.get(&coord).copied().unwrap_or(0.0);
+            let (dx, dy) = direction.unwrap_or((1.0, 0.0));
+            let num_ticks = if ticks_rendered.insert((station_idx, coord)) {
+                (2.0 * half_width / spacing).round() as usize + 1
+            } else {
+                0
+            };
+            for k in 0..num_ticks {
+                let offset = k as f64 * spacing - half_width;
+                let (ox, oy) = (-dy * offset, dx * offset);
+                polylines.add_polyline(&[
+                    PhysicalCoord {
+                        x: p0.x + ox,
+                        y: p0.y + oy,
+                    },
+                    PhysicalCoord {
+                        x: p1.x + ox,
+                        y: p1.y + oy,
+                    },
+                ]);
+            }
 
-                    let station = &self[station_idx];
-                    let pt = viewport.to_physical_point(railway_points[i].coord);
-                    stations.push(StationRenderingInfo {
-                        name: station.name.clone(),
-                        x: pt.x,
-                        y: pt.y,
-                    });
-                }
+            if station_rendered.contains(&station_idx) {
+                continue;
             }
+            station_rendered.insert(station_idx);
+
+            let station = &self[station_idx];
+            let pt = viewport.to_physical_point(coord);
+            stations.push(StationRenderingInfo {
+                name: station.name.clone(),
+                x: pt.x,
+                y: pt.y,
+            });
         }
 
-        if station_points.len() > 0 {
-            rail_colors.push(Color {
+        polylines.end_group(
+            Color {
                 r: 148,
                 g: 148,
                 b: 148,
-            });
-            rail_width.push(4);
-            rail_style.push(0);
-            rail_points_num.push(station_points.len() as i32);
-            rail_points.extend(station_points);
-        }
+            },
+            4,
+            0,
+        );
 
-        let mut border_points = vec![vec![]; 3];
+        let mut border_polylines = vec![vec![]; 3];
 
         let get_border_coord = |i: BorderPointIndex| {
+            if let Some(&coord) = moved_border_points.get(&i) {
+                return coord;
+            }
             match opts.temporary_moving_border_point {
                 None => (),
-                Some(mv) => match mv.point_or_segment {
-                    BorderPointOrSegment::Point(p) => {
+                Some(mv) => {
+                    if let BorderPointOrSegment::Point(p) = mv.point_or_segment {
                         if i == p {
                             return viewport.from_physical_point(mv.point_after_move);
                         }
                     }
-                    _ => (),
-                },
+                }
             }
             self.border_points[i].coord
         };
 
+        let mut visible_border_points = self
+            .geometry_index
+            .border_points
+            .query(&viewport.bounding_box);
+        let mut visible_border_segments = self
+            .geometry_index
+            .border_segments
+            .query(&viewport.bounding_box);
+        for &p in moved_border_points.keys() {
+            visible_border_points.push(p);
+            for &(j, _) in &self.border_points[p].neighbors {
+                visible_border_segments.push(border_segment_key(p, j));
+            }
+        }
+        if !moved_border_points.is_empty() {
+            visible_border_points.sort();
+            visible_border_points.dedup();
+            visible_border_segments.sort();
+            visible_border_segments.dedup();
+        }
         let mut temporary_segment_level = None;
-        for (i, pt) in self.border_points.enumerate() {
-            if opts.marker_on_border_points {
+        if let Some(mv) = opts.temporary_moving_border_point {
+            match mv.point_or_segment {
+                BorderPointOrSegment::Point(p) => {
+                    // The index knows only the position before the move
+                    visible_border_points.push(p);
+                    for &(j, _) in &self.border_points[p].neighbors {
+                        visible_border_segments.push(border_segment_key(p, j));
+                    }
+                    visible_border_points.sort();
+                    visible_border_points.dedup();
+                    visible_border_segments.sort();
+                    visible_border_segments.dedup();
+                }
+                BorderPointOrSegment::Segment(x, y) => {
+                    temporary_segment_level = self.border_points[x].get_level(y);
+                }
+            }
+        }
+
+        if opts.marker_on_border_points {
+            for i in visible_border_points {
                 let coord = get_border_coord(i);
                 if viewport.contains(coord) {
                     marker_points.push(viewport.to_physical_point(coord));
                 }
             }
-            for &(j, level) in &pt.neighbors {
-                if i < j {
-                    assert!(level < 3);
-
-                    if let Some(TemporaryMovingBorderPoint {
-                        point_or_segment: BorderPointOrSegment::Segment(x, y),
-                        point_after_move: _,
-                    }) = opts.temporary_moving_border_point
-                    {
-                        if (i, j) == (x, y) || (j, i) == (x, y) {
-                            temporary_segment_level = Some(level);
-                            continue;
-                        }
-                    }
+        }
+        let mut border_segments = vec![];
+        for (i, j) in visible_border_segments {
+            let level = self.border_points[i].get_level(j).unwrap();
+            assert!(level < 3);
 
-                    let c1 = get_border_coord(i);
-                    let c2 = get_border_coord(j);
-                    if viewport.crosses_with_line_segment(c1, c2) {
-                        border_points[level as usize].push(viewport.to_physical_point(c1));
-                        border_points[level as usize].push(viewport.to_physical_point(c2));
-                    }
+            if let Some(TemporaryMovingBorderPoint {
+                point_or_segment: BorderPointOrSegment::Segment(x, y),
+                point_after_move: _,
+            }) = opts.temporary_moving_border_point
+            {
+                if (i, j) == (x, y) || (j, i) == (x, y) {
+                    continue;
                 }
             }
+
+            let c1 = get_border_coord(i);
+            let c2 = get_border_coord(j);
+            if viewport.crosses_with_line_segment(c1, c2) {
+                border_segments.push((i, j, level));
+            }
+        }
+        // Borders are rendered as is while they are being edited
+        let tolerance = if opts.marker_on_border_points {
+            0.0
+        } else {
+            SIMPLIFICATION_TOLERANCE * viewport.zoom
+        };
+        for (level, chain) in border_chains(&border_segments) {
+            let coords = chain
+                .iter()
+                .map(|&i| get_border_coord(i))
+                .collect::<Vec<_>>();
+            let retained = simplify_polyline(&coords, &vec![false; coords.len()], tolerance);
+            border_polylines[level as usize].push(
+                retained
+                    .into_iter()
+                    .map(|k| viewport.to_physical_point(coords[k]))
+                    .collect::<Vec<_>>(),
+            );
         }
         if let Some(level) = temporary_segment_level {
             let temporary_moving_border_point = opts.temporary_moving_border_point.unwrap();
@@ -859,47 +2208,48 @@ impl RerailMap {
             if let BorderPointOrSegment::Segment(i, j) = ps {
                 let c1 = get_border_coord(i);
                 let c2 = get_border_coord(j);
-                border_points[level as usize].push(viewport.to_physical_point(c1));
-                border_points[level as usize].push(temporary_moving_border_point.point_after_move);
-                border_points[level as usize].push(viewport.to_physical_point(c2));
-                border_points[level as usize].push(temporary_moving_border_point.point_after_move);
+                border_polylines[level as usize].push(vec![
+                    viewport.to_physical_point(c1),
+                    temporary_moving_border_point.point_after_move,
+                    viewport.to_physical_point(c2),
+                ]);
             }
         }
         if let Some(extra) = opts.extra_border_segment {
             let level = extra.level as usize;
             assert!(level < 3);
-            border_points[level]
-                .push(viewport.to_physical_point(self.border_points[extra.point].coord));
-            border_points[level].push(extra.new_point);
+            border_polylines[level].push(vec![
+                viewport.to_physical_point(self.border_points[extra.point].coord),
+                extra.new_point,
+            ]);
         }
 
-        for level in 0..3 {
-            if border_points[level].len() > 0 {
-                rail_colors.push(Color { r: 0, g: 0, b: 0 });
-
-                let (width, style) = match level {
-                    0 => (1, 1),
-                    1 => (1, 0),
-                    2 => (2, 0),
-                    _ => unreachable!(),
-                };
-                rail_width.push(width);
-                rail_style.push(style);
-                rail_points_num.push(border_points[level].len() as i32);
-                rail_points.extend(&border_points[level]);
+        for (level, border_polylines) in border_polylines.iter().enumerate() {
+            for polyline in border_polylines {
+                polylines.add_polyline(polyline);
             }
+
+            let (width, style) = match level {
+                0 => (1, 1),
+                1 => (1, 0),
+                2 => (2, 0),
+                _ => unreachable!(),
+            };
+            polylines.end_group(Color { r: 0, g: 0, b: 0 }, width, style);
         }
 
-        let (rail_points_x, rail_points_y) = split_into_x_and_y(&rail_points);
+        let (rail_points_x, rail_points_y) = split_into_x_and_y(&polylines.points);
         let (marker_points_x, marker_points_y) = split_into_x_and_y(&marker_points);
 
-        assert_eq!(rail_width.len(), rail_style.len());
+        assert_eq!(polylines.width.len(), polylines.style.len());
 
         RenderingInfo {
-            rail_colors,
-            rail_width,
-            rail_style,
-            rail_points_num: rail_points_num.into_boxed_slice(),
+            rail_colors: polylines.colors,
+            rail_width: polylines.width,
+            rail_style: polylines.style,
+            rail_polylines_num: polylines.polylines_num.into_boxed_slice(),
+            polyline_start: polylines.polyline_start.into_boxed_slice(),
+            polyline_points_num: polylines.polyline_points_num.into_boxed_slice(),
             rail_points_x: rail_points_x.into_boxed_slice(),
             rail_points_y: rail_points_y.into_boxed_slice(),
             marker_points_x,
@@ -913,26 +2263,21 @@ impl RerailMap {
         &self,
         viewport: ViewportSpec,
         rail_id: RailwayIndex,
-        x: i32,
-        y: i32,
-        max_dist: i32,
+        x: f64,
+        y: f64,
+        max_dist: f64,
     ) -> Option<IndexOnRailway> {
         let viewport = Viewport::new(viewport);
-        let p = Coord::new(x, y);
+        let p = viewport.from_physical_point(PhysicalCoord { x, y });
 
-        let threshold = max_dist as i64 * max_dist as i64;
+        let threshold = viewport.logical_distance_sq(max_dist);
 
         let railway = self.railways.get(rail_id)?;
 
-        let mut nearest = (threshold + 1, 0);
+        let mut nearest = (f64::INFINITY, 0);
 
         for i in 0..railway.points.len() {
-            let d = distance_norm_square_points(
-                viewport
-                    .to_physical_point(railway.points[i].coord)
-                    .as_coord(),
-                p,
-            );
+            let d = distance_norm_square_points(railway.points[i].coord, p) as f64;
             if d < nearest.0 {
                 nearest = (d, i);
             }
@@ -945,18 +2290,15 @@ impl RerailMap {
             });
         }
 
-        let mut nearest = (threshold + 1, 0);
+        let mut nearest = (f64::INFINITY, 0);
 
-        for i in 1..railway.points.len() {
-            let c0 = viewport
-                .to_physical_point(railway.points[i - 1].coord)
-                .as_coord();
-            let c1 = viewport
-                .to_physical_point(railway.points[i].coord)
-                .as_coord();
-            let d = distance_norm_square_point_line_segment(c0, c1, p);
-            if d < nearest.0 {
-                nearest = (d, i);
+        for i in 0..railway.points.len() {
+            if railway.has_segment(i) {
+                let d = railway.distance_norm_square_to_segment(i, p) as f64;
+                if d < nearest.0 {
+                    // A point inserted at `i + 1` lies on the segment, even on the closing one of a ring
+                    nearest = (d, i + 1);
+                }
             }
         }
 
@@ -974,22 +2316,21 @@ impl RerailMap {
     pub fn find_nearest_border(
         &self,
         viewport: ViewportSpec,
-        x: i32,
-        y: i32,
-        max_dist: i32,
+        x: f64,
+        y: f64,
+        max_dist: f64,
     ) -> Option<BorderPointOrSegment> {
         let viewport = Viewport::new(viewport);
-        let p = Coord::new(x, y);
+        let p = viewport.from_physical_point(PhysicalCoord { x, y });
 
-        let threshold = max_dist as i64 * max_dist as i64;
+        let threshold = viewport.logical_distance_sq(max_dist);
+        let radius = viewport.logical_radius(max_dist);
 
         let mut nearest = None;
-        for (i, pt) in self.border_points.enumerate() {
-            let d = distance_norm_square_points(viewport.to_physical_point(pt.coord).as_coord(), p);
-            if d <= threshold {
-                if nearest.map(|(d2, _)| d < d2).unwrap_or(true) {
-                    nearest = Some((d, i));
-                }
+        for i in self.geometry_index.border_points.query_around(p, radius) {
+            let d = distance_norm_square_points(self.border_points[i].coord, p) as f64;
+            if d <= threshold && nearest.map(|(d2, _)| d < d2).unwrap_or(true) {
+                nearest = Some((d, i));
             }
         }
         if let Some((_, p)) = nearest {
@@ -997,18 +2338,12 @@ impl RerailMap {
         }
 
         let mut nearest = None;
-        for (i, pt) in self.border_points.enumerate() {
-            let c0 = viewport.to_physical_point(pt.coord).as_coord();
-            for (j, _) in &pt.neighbors {
-                let c1 = viewport
-                    .to_physical_point(self.border_points[*j].coord)
-                    .as_coord();
-                let d = distance_norm_square_point_line_segment(c0, c1, p);
-                if d <= threshold {
-                    if nearest.map(|(d2, _)| d < d2).unwrap_or(true) {
-                        nearest = Some((d, (i, *j)));
-                    }
-                }
+        for (i, j) in self.geometry_index.border_segments.query_around(p, radius) {
+            let c0 = self.border_points[i].coord;
+            let c1 = self.border_points[j].coord;
+            let d = distance_norm_square_point_line_segment(c0, c1, p) as f64;
+            if d <= threshold && nearest.map(|(d2, _)| d < d2).unwrap_or(true) {
+                nearest = Some((d, (i, j)));
             }
         }
         if let Some((_, (i, j))) = nearest {
@@ -1018,6 +2353,287 @@ impl RerailMap {
         None
     }
 
+    /// Finds all the entities visible in the viewport within `radius` physical pixels from `(x, y)`.
+    /// Hits are ranked by the kind of the entity (stations, railway points, border points, railway
+    /// segments and then border segments) and then by the distance.
+    pub fn pick(&self, viewport: ViewportSpec, x: f64, y: f64, radius: f64) -> PickResult {
+        let viewport = Viewport::new(viewport);
+        let p = viewport.from_physical_point(PhysicalCoord { x, y });
+
+        PickResult {
+            hits: self
+                .pick_hits(&viewport, p, radius)
+                .into_iter()
+                .map(|(entity, dist_sq)| PickHit {
+                    entity,
+                    distance: dist_sq.sqrt() / viewport.zoom,
+                })
+                .collect(),
+        }
+    }
+
+    /// Snaps the position `(x, y)` in the physical coordinate to nearby entities, or otherwise to
+    /// the directions of multiples of 45 degrees from the previous point or to the grid.
+    /// Entities take precedence in the same order as in `pick`.
+    pub fn snap(&self, viewport: ViewportSpec, x: f64, y: f64, options: SnapOptions) -> SnapResult {
+        let viewport = Viewport::new(viewport);
+        let p = viewport.from_physical_point(PhysicalCoord { x, y });
+
+        let editing_rail = options.rail_id.map(RailwayIndex::from_usize);
+        let moving = editing_rail.zip(
+            options
+                .editing_index
+                .as_ref()
+                .filter(|idx| !idx.inserting)
+                .map(|idx| idx.index),
+        );
+
+        for (entity, _) in self.pick_hits(&viewport, p, options.radius) {
+            let (coord, target) = match entity {
+                PickedEntity::Station(station_idx, rail_id, i) => {
+                    if moving == Some((rail_id, i)) {
+                        continue;
+                    }
+                    (
+                        self.railways[rail_id].points[i].coord,
+                        SnapTarget::Station(station_idx),
+                    )
+                }
+                PickedEntity::RailwayPoint(rail_id, i) => {
+                    if moving == Some((rail_id, i)) {
+                        continue;
+                    }
+                    (
+                        self.railways[rail_id].points[i].coord,
+                        SnapTarget::RailwayPoint(rail_id, i),
+                    )
+                }
+                PickedEntity::BorderPoint(i) => {
+                    if options.border_point == Some(i) {
+                        continue;
+                    }
+                    (self.border_points[i].coord, SnapTarget::BorderPoint(i))
+                }
+                PickedEntity::RailwaySegment(rail_id, i) => {
+                    if editing_rail == Some(rail_id) {
+                        continue;
+                    }
+                    (
+                        self.railways[rail_id].nearest_point_on_segment(i, p),
+                        SnapTarget::RailwaySegment(rail_id, i),
+                    )
+                }
+                PickedEntity::BorderSegment(..) => continue,
+            };
+            return SnapResult {
+                x: coord.x,
+                y: coord.y,
+                target,
+            };
+        }
+
+        if options.angle {
+            if let Some(prev) = self.previous_point_for_snap(&options) {
+                let c = snap_to_45_degrees(prev, p);
+                if distance_norm_square_points(c, p) as f64
+                    <= viewport.logical_distance_sq(options.radius)
+                {
+                    return SnapResult {
+                        x: c.x,
+                        y: c.y,
+                        target: SnapTarget::Angle,
+                    };
+                }
+            }
+        }
+
+        if let Some(grid) = options.grid.filter(|&g| g > 0) {
+            let round = |v: i32| ((v as f64 / grid as f64).round() * grid as f64) as i32;
+            return SnapResult {
+                x: round(p.x),
+                y: round(p.y),
+                target: SnapTarget::Grid,
+            };
+        }
+
+        SnapResult {
+            x: p.x,
+            y: p.y,
+            target: SnapTarget::None,
+        }
+    }
+
+    /// Selects the entities of the railways visible in the viewport and border points inside the
+    /// rectangle with corners `(x0, y0)` and `(x1, y1)` in the physical coordinate.
+    #[wasm_bindgen(js_name = selectInRectangle)]
+    pub fn select_in_rectangle(
+        &self,
+        viewport: ViewportSpec,
+        x0: f64,
+        y0: f64,
+        x1: f64,
+        y1: f64,
+    ) -> Selection {
+        let viewport = Viewport::new(viewport);
+        let rect = Rect::from_corners(
+            viewport.from_physical_point(PhysicalCoord { x: x0, y: y0 }),
+            viewport.from_physical_point(PhysicalCoord { x: x1, y: y1 }),
+        );
+        self.select_by(&viewport, &rect, |c| rect.contains(c))
+    }
+
+    /// Selects the entities inside the polygon with vertices `(xs[i], ys[i])` in the physical
+    /// coordinate, in the same way as `select_in_rectangle`.
+    #[wasm_bindgen(js_name = selectInPolygon)]
+    pub fn select_in_polygon(
+        &self,
+        viewport: ViewportSpec,
+        xs: Vec<f64>,
+        ys: Vec<f64>,
+    ) -> Selection {
+        let viewport = Viewport::new(viewport);
+        let polygon = xs
+            .into_iter()
+            .zip(ys)
+            .map(|(x, y)| viewport.from_physical_point(PhysicalCoord { x, y }))
+            .collect::<Vec<_>>();
+        let rect = match bounding_box(polygon.iter().copied()) {
+            Some(b) => Rect::new(b.top, b.bottom, b.left, b.right),
+            None => return Selection::default(),
+        };
+        self.select_by(&viewport, &rect, |c| polygon_contains(&polygon, c))
+    }
+
+    /// Moves all the points in the selection by `(dx, dy)` in the logical coordinate.
+    #[wasm_bindgen(js_name = translateSelection)]
+    pub fn translate_selection(mut self, selection: Selection, dx: i32, dy: i32) -> RerailMap {
+        // Points welded to the selected ones move together
+        let mut rail_ids = vec![];
+        for (rail_id, i) in self.with_welded_points(&selection.railway_points) {
+            if let Some(pt) = self
+                .railways
+                .get_mut(rail_id)
+                .and_then(|railway| railway.points.get_mut(i))
+            {
+                pt.coord = translate(pt.coord, (dx, dy));
+                rail_ids.push(rail_id);
+            }
+        }
+        self.reindex_railways(rail_ids);
+
+        for &i in &selection.border_points {
+            if let Some(pt) = self.border_points.get_mut(i) {
+                pt.coord = translate(pt.coord, (dx, dy));
+            }
+        }
+        for &i in &selection.border_points {
+            if self.border_points.get(i).is_some() {
+                self.reindex_border_point(i);
+            }
+        }
+
+        self
+    }
+
+    /// Removes all the points in the selection. Railways without remaining points are removed.
+    /// Unlike `remove_border_point`, border segments incident to the removed border points are
+    /// removed rather than bridged.
+    #[wasm_bindgen(js_name = deleteSelection)]
+    pub fn delete_selection(mut self, selection: Selection) -> RerailMap {
+        for (rail_id, mut indices) in group_by_railway(selection.railway_points) {
+            let railway = match self.railways.get_mut(rail_id) {
+                Some(railway) => railway,
+                None => continue,
+            };
+            indices.sort();
+            indices.dedup();
+
+            let mut detached = vec![];
+            for &i in indices.iter().rev() {
+                if i < railway.points.len() {
+                    detached.extend(railway.points[i].station);
+                    railway.remove_point(i);
+                }
+            }
+            for station_idx in detached {
+                let remains = self.railways[rail_id]
+                    .points
+                    .iter()
+                    .any(|pt| pt.station == Some(station_idx));
+                if !remains
+                    && self[station_idx].remove_railway(rail_id)
+                    && self[station_idx].railways.is_empty()
+                {
+                    self.stations.delete(station_idx);
+                }
+            }
+            if self.railways[rail_id].points.is_empty() {
+                self.railways.delete(rail_id);
+            }
+            self.reindex_railway(rail_id);
+        }
+
+        for &i in &selection.border_points {
+            let neighbors = match self.border_points.get(i) {
+                Some(pt) => pt.neighbors.iter().map(|&(j, _)| j).collect::<Vec<_>>(),
+                None => continue,
+            };
+            if neighbors.is_empty() {
+                self.border_points.delete(i);
+                self.reindex_border_point(i);
+            }
+            // Neighbors left without any edge are removed as well
+            for j in neighbors {
+                self = self.remove_border_edge(i, j);
+            }
+        }
+
+        self
+    }
+
+    /// Sets the level of the stations in the selection, of the border segments both of whose
+    /// endpoints are selected, and of the railways all of whose points are selected.
+    /// Levels out of range for an entity are ignored.
+    #[wasm_bindgen(js_name = setSelectionLevel)]
+    pub fn set_selection_level(mut self, selection: Selection, level: u8) -> RerailMap {
+        for &station_idx in &selection.stations {
+            if let Some(station) = self.stations.get_mut(station_idx) {
+                if (level as usize) < STATION_THRESHOLD[0].len() {
+                    station.level = level;
+                }
+            }
+        }
+
+        if level < 3 {
+            for &i in &selection.border_points {
+                let neighbors = match self.border_points.get(i) {
+                    Some(pt) => pt.neighbors.iter().map(|&(j, _)| j).collect::<Vec<_>>(),
+                    None => continue,
+                };
+                for j in neighbors {
+                    if selection.border_points.contains(&j) {
+                        self.border_points[i].update_level(j, level);
+                        self.border_points[j].update_level(i, level);
+                    }
+                }
+            }
+        }
+
+        if (level as usize) < RAILWAY_THRESHOLD.len() {
+            for (rail_id, mut indices) in group_by_railway(selection.railway_points) {
+                if let Some(railway) = self.railways.get_mut(rail_id) {
+                    indices.sort();
+                    indices.dedup();
+                    if indices.len() == railway.points.len() {
+                        railway.level = level;
+                    }
+                }
+            }
+        }
+
+        self
+    }
+
     #[wasm_bindgen(js_name = getNumberOfPointsOnRailway)]
     pub fn get_number_of_points_on_railway(&self, rail_id: RailwayIndex) -> usize {
         self.railways[rail_id].points.len()
@@ -1052,12 +2668,14 @@ impl RerailMap {
                 let station_idx = self.stations.push(Station::new(info.name, info.level));
                 railway.points[point_idx].station = Some(station_idx);
                 self[station_idx].add_railway(rail_id);
+                self.reindex_railway(rail_id);
             }
         }
         self
     }
 
     #[wasm_bindgen(js_name = getRailwayInfo)]
+    #[allow(clippy::identity_op)]
     pub fn get_railway_info(&self, rail_id: RailwayIndex) -> RailwayInfo {
         let railway = &self.railways[rail_id];
         RailwayInfo {
@@ -1066,12 +2684,16 @@ impl RerailMap {
             color: ((railway.color.r as u32) << 16)
                 | ((railway.color.g as u32) << 8)
                 | ((railway.color.b as u32) << 0),
+            metadata: railway.metadata.clone(),
         }
     }
 
     #[wasm_bindgen(js_name = setRailwayInfo)]
+    #[allow(clippy::identity_op)]
     pub fn set_railway_info(mut self, rail_id: RailwayIndex, info: RailwayInfo) -> RerailMap {
+        let metadata = self.validate_metadata(info.metadata);
         let railway = &mut self.railways[rail_id];
+        railway.metadata = metadata;
         railway.name = info.name;
         railway.level = info.level;
         railway.color = Color {
@@ -1083,6 +2705,7 @@ impl RerailMap {
     }
 
     #[wasm_bindgen(js_name = newRailwayFromInfo)]
+    #[allow(clippy::identity_op)]
     pub fn new_railway_from_info(
         mut self,
         info: RailwayInfo,
@@ -1100,94 +2723,3086 @@ impl RerailMap {
             points: vec![RailwayPoint {
                 coord: Coord::new(x, y),
                 station: None,
+                weld: None,
+                official_distance: None,
             }],
+            branch_starts: vec![],
+            closed: false,
+            smooth: false,
+            metadata: self.validate_metadata(info.metadata),
         };
         let index = self.railways.push(railway);
+        self.reindex_railway(index);
         RerailMapAndRailwayIndex {
             map: Some(self),
             index,
         }
     }
 
+    #[wasm_bindgen(js_name = addOperator)]
+    pub fn add_operator(mut self, name: String) -> RerailMapAndOperatorIndex {
+        let index = self.operators.push(Operator { name });
+        RerailMapAndOperatorIndex {
+            map: Some(self),
+            index,
+        }
+    }
+
+    #[wasm_bindgen(js_name = renameOperator)]
+    pub fn rename_operator(mut self, id: OperatorIndex, name: String) -> RerailMap {
+        if let Some(operator) = self.operators.get_mut(id) {
+            operator.name = name;
+        }
+        self
+    }
+
+    /// Removes the operator, leaving the operators of its railways unknown.
+    #[wasm_bindgen(js_name = removeOperator)]
+    pub fn remove_operator(mut self, id: OperatorIndex) -> RerailMap {
+        if self.operators.get(id).is_none() {
+            return self;
+        }
+        let rail_ids = self
+            .railways
+            .enumerate()
+            .filter(|(_, railway)| railway.metadata.operator == Some(id))
+            .map(|(rail_id, _)| rail_id)
+            .collect::<Vec<_>>();
+        for rail_id in rail_ids {
+            self.railways[rail_id].metadata.operator = None;
+        }
+        self.operators.delete(id);
+        self
+    }
+
+    #[wasm_bindgen(js_name = operatorList)]
+    pub fn operator_list(&self) -> OperatorList {
+        let mut operators = self
+            .operators
+            .enumerate()
+            .map(|(id, operator)| OperatorInfo {
+                id,
+                name: operator.name.clone(),
+            })
+            .collect::<Vec<_>>();
+        operators.sort_by_key(|op| op.id);
+        OperatorList { operators }
+    }
+
     #[wasm_bindgen(js_name = removeRailway)]
     pub fn remove_railway(mut self, rail_id: RailwayIndex) -> RerailMap {
-        let railway = &self.railways[rail_id];
-        for pt in &railway.points {
-            if let Some(station_idx) = pt.station {
-                self.stations[station_idx].remove_railway(rail_id);
-                if self.stations[station_idx].railways.is_empty() {
-                    self.stations.delete(station_idx);
-                }
+        // A station appears more than once on the railway at a junction of branches
+        let mut station_ids = self.railways[rail_id]
+            .points
+            .iter()
+            .filter_map(|pt| pt.station)
+            .collect::<Vec<_>>();
+        station_ids.sort();
+        station_ids.dedup();
+        for station_idx in station_ids {
+            self.stations[station_idx].remove_railway(rail_id);
+            if self.stations[station_idx].railways.is_empty() {
+                self.stations.delete(station_idx);
             }
         }
         self.railways.delete(rail_id);
+        self.reindex_railway(rail_id);
         self
     }
 
-    #[wasm_bindgen(js_name = stationListOnRailway)]
-    pub fn station_list_on_railway(&self, rail_id: RailwayIndex) -> StationListOnRailway {
-        let railway = &self.railways[rail_id];
-        let mut cur_distance = 0.0f64;
-        let mut names = vec![];
-        let mut distances = vec![];
+    /// Splits the railway at the `point_idx`-th point into two railways sharing that point.
+    /// The first half stays in `rail_id` and the second half is moved to a new railway with the same
+    /// name, color and level. If the point is an endpoint of the railway, nothing is changed and
+    /// `rail_id` is returned as the railway index.
+    /// A loop line is instead cut at the point, if it is on the ring, keeping `rail_id` as is.
+    #[wasm_bindgen(js_name = splitRailway)]
+    pub fn split_railway(
+        mut self,
+        rail_id: RailwayIndex,
+        point_idx: usize,
+    ) -> RerailMapAndRailwayIndex {
+        let railway = &mut self.railways[rail_id];
+        if railway.closed && point_idx < railway.first_branch_end() {
+            railway.open_at(point_idx);
+            self.reindex_railway(rail_id);
+            return RerailMapAndRailwayIndex {
+                map: Some(self),
+                index: rail_id,
+            };
+        }
+        let ranges = railway.branch_ranges();
+        let branch = match ranges.iter().position(|r| r.contains(&point_idx)) {
+            Some(k) if ranges[k].start < point_idx && point_idx + 1 < ranges[k].end => k,
+            _ => {
+                return RerailMapAndRailwayIndex {
+                    map: Some(self),
+                    index: rail_id,
+                }
+            }
+        };
 
-        for i in 0..railway.points.len() {
-            if i > 0 {
-                let dist_sq = distance_norm_square_points(
-                    railway.points[i - 1].coord,
-                    railway.points[i].coord,
-                );
-                cur_distance += (dist_sq as f64).sqrt();
+        // The rest of the branch moves to the new railway, together with the branches leaving from
+        // the moved points
+        let mut moved = vec![false; railway.points.len()];
+        moved[point_idx + 1..ranges[branch].end].fill(true);
+        for r in &ranges[branch + 1..] {
+            if railway.branch_origin(r.start).is_some_and(|j| moved[j]) {
+                moved[r.clone()].fill(true);
+            }
+        }
+
+        let mut points = vec![railway.points[point_idx].clone()];
+        let mut branch_starts = vec![];
+        let mut kept_points = vec![];
+        let mut kept_branch_starts = vec![];
+        for (i, pt) in std::mem::take(&mut railway.points).into_iter().enumerate() {
+            let (points, branch_starts) = if moved[i] {
+                (&mut points, &mut branch_starts)
+            } else {
+                (&mut kept_points, &mut kept_branch_starts)
+            };
+            if railway.branch_starts.contains(&i) {
+                branch_starts.push(points.len());
+            }
+            points.push(pt);
+        }
+        railway.points = kept_points;
+        railway.branch_starts = kept_branch_starts;
+        let new_railway = Railway {
+            name: railway.name.clone(),
+            color: railway.color,
+            level: railway.level,
+            points,
+            branch_starts,
+            closed: false,
+            smooth: railway.smooth,
+            metadata: railway.metadata.clone(),
+        };
+        let index = self.railways.push(new_railway);
+
+        let mut moved_stations = vec![];
+        for pt in &self.railways[index].points {
+            if let Some(station_idx) = pt.station {
+                moved_stations.push(station_idx);
             }
-            if let Some(id) = railway.points[i].station {
-                let station = &self[id];
-                names.push(station.name.clone());
-                distances.push(cur_distance);
+        }
+        for station_idx in moved_stations {
+            let remains = self.railways[rail_id]
+                .points
+                .iter()
+                .any(|pt| pt.station == Some(station_idx));
+            if !remains {
+                self[station_idx].remove_railway(rail_id);
             }
+            self[station_idx].add_railway(index);
         }
 
-        StationListOnRailway { names, distances }
+        // The moved welds are indexed on the new railway before they are removed from the old one
+        self.reindex_railway(index);
+        self.reindex_railway(rail_id);
+        RerailMapAndRailwayIndex {
+            map: Some(self),
+            index,
+        }
     }
-}
 
-impl Index<StationIndex> for RerailMap {
-    type Output = Station;
+    /// Joins the railway `b` to `a` end-to-end and removes `b`.
+    /// The closest pair of endpoints of the railways is connected, and the two endpoints are unified
+    /// into one point if they are at the same position and do not have different stations or welds.
+    /// The merged railway keeps the name and color of `a`, and takes the higher level of the two.
+    /// If `b` has to be reversed, its official distances are cleared, and if `b` is put before `a`,
+    /// the official distances of `a` are cleared, since they are measured from the old start of
+    /// `a`. Loop lines are not merged.
+    #[wasm_bindgen(js_name = mergeRailways)]
+    pub fn merge_railways(mut self, a: RailwayIndex, b: RailwayIndex) -> RerailMap {
+        let mut other = self.railways[b].clone();
+        let a_points = &self.railways[a].points;
+        if a == b
+            || a_points.is_empty()
+            || other.points.is_empty()
+            || self.railways[a].closed
+            || other.closed
+        {
+            return self;
+        }
+        let a_last_idx = self.railways[a].first_branch_end() - 1;
+        let (a_first, a_last) = (a_points[0].coord, a_points[a_last_idx].coord);
+        let (b_first, b_last) = (
+            other.points[0].coord,
+            other.points[other.first_branch_end() - 1].coord,
+        );
 
-    fn index(&self, index: StationIndex) -> &Self::Output {
-        &self.stations[index]
-    }
-}
+        // (distance, whether `b` is appended after `a`, whether `b` is reversed)
+        let candidates = [
+            (distance_norm_square_points(a_last, b_first), true, false),
+            (distance_norm_square_points(a_last, b_last), true, true),
+            (distance_norm_square_points(a_first, b_last), false, false),
+            (distance_norm_square_points(a_first, b_first), false, true),
+        ];
+        let &(_, append, reverse) = candidates.iter().min_by_key(|c| c.0).unwrap();
+        if reverse {
+            other.reverse();
+        }
 
-impl Index<RailwayIndex> for RerailMap {
-    type Output = Railway;
+        let railway = &mut self.railways[a];
+        railway.level = railway.level.max(other.level);
 
-    fn index(&self, index: RailwayIndex) -> &Self::Output {
-        &self.railways[index]
+        let (a_end, b_end) = if append {
+            (a_last_idx, 0)
+        } else {
+            (0, other.first_branch_end() - 1)
+        };
+        let a_end_pt = &railway.points[a_end];
+        let b_end_pt = &other.points[b_end];
+        if a_end_pt.coord == b_end_pt.coord
+            && (a_end_pt.station.is_none()
+                || b_end_pt.station.is_none()
+                || a_end_pt.station == b_end_pt.station)
+            && (a_end_pt.weld.is_none()
+                || b_end_pt.weld.is_none()
+                || a_end_pt.weld == b_end_pt.weld)
+        {
+            let station = a_end_pt.station.or(b_end_pt.station);
+            let weld = a_end_pt.weld.or(b_end_pt.weld);
+            railway.points[a_end].station = station;
+            railway.points[a_end].weld = weld;
+            other.remove_point(b_end);
+        }
+
+        if !append {
+            // Put the points of `b` first
+            for pt in &mut railway.points {
+                pt.official_distance = None;
+            }
+            std::mem::swap(&mut railway.points, &mut other.points);
+            std::mem::swap(&mut railway.branch_starts, &mut other.branch_starts);
+        }
+        railway.append(other);
+
+        let mut stations = vec![];
+        for pt in &self.railways[a].points {
+            if let Some(station_idx) = pt.station {
+                stations.push(station_idx);
+            }
+        }
+        for station_idx in stations {
+            self[station_idx].remove_railway(b);
+            self[station_idx].add_railway(a);
+        }
+
+        self.railways.delete(b);
+        self.reindex_railway(a);
+        self.reindex_railway(b);
+        self
     }
-}
 
-impl Index<BorderPointIndex> for RerailMap {
-    type Output = BorderPoint;
+    /// Adds a new branch to the railway which starts at the `point_idx`-th point.
+    /// The new branch consists of a copy of the point (with its station) appended at the end of the
+    /// points, so that the following `insert_railway_point` at the end extends the branch.
+    /// The copy is welded to the point, so that the junction moves as one point.
+    #[wasm_bindgen(js_name = addRailwayBranch)]
+    pub fn add_railway_branch(mut self, rail_id: RailwayIndex, point_idx: usize) -> RerailMap {
+        if self.railways[rail_id].points[point_idx].weld.is_none() {
+            let weld = self.new_weld_id();
+            self.railways[rail_id].points[point_idx].weld = Some(weld);
+        }
+        let railway = &mut self.railways[rail_id];
+        let point = railway.points[point_idx].clone();
+        railway.branch_starts.push(railway.points.len());
+        railway.points.push(point);
+        self.reindex_railway(rail_id);
+        self
+    }
 
-    fn index(&self, index: BorderPointIndex) -> &Self::Output {
-        &self.border_points[index]
+    #[wasm_bindgen(js_name = isRailwaySmooth)]
+    pub fn is_railway_smooth(&self, rail_id: RailwayIndex) -> bool {
+        self.railways[rail_id].smooth
     }
-}
 
-impl IndexMut<StationIndex> for RerailMap {
-    fn index_mut(&mut self, index: StationIndex) -> &mut Self::Output {
-        &mut self.stations[index]
+    /// Sets whether the railway is rendered as a smooth curve through its points rather than a
+    /// polyline. The points themselves are not changed.
+    #[wasm_bindgen(js_name = setRailwaySmooth)]
+    pub fn set_railway_smooth(mut self, rail_id: RailwayIndex, smooth: bool) -> RerailMap {
+        self.railways[rail_id].smooth = smooth;
+        self.reindex_railway(rail_id);
+        self
     }
-}
 
-impl IndexMut<RailwayIndex> for RerailMap {
-    fn index_mut(&mut self, index: RailwayIndex) -> &mut Self::Output {
-        &mut self.railways[index]
+    #[wasm_bindgen(js_name = isRailwayClosed)]
+    pub fn is_railway_closed(&self, rail_id: RailwayIndex) -> bool {
+        self.railways[rail_id].closed
     }
-}
 
-impl IndexMut<BorderPointIndex> for RerailMap {
-    fn index_mut(&mut self, index: BorderPointIndex) -> &mut Self::Output {
-        &mut self.border_points[index]
+    /// Makes the railway a loop line by connecting the last point of its first branch to the first
+    /// point, or cuts the loop at the first point.
+    #[wasm_bindgen(js_name = setRailwayClosed)]
+    pub fn set_railway_closed(mut self, rail_id: RailwayIndex, closed: bool) -> RerailMap {
+        let railway = &mut self.railways[rail_id];
+        if closed {
+            railway.close();
+        } else {
+            railway.open_at(0);
+        }
+        self.reindex_railway(rail_id);
+        self
+    }
+
+    /// Reverses the direction of the railway. Stations stay linked to the same points.
+    /// Only the first branch is reversed: a ring keeps its first point, and the other branches keep
+    /// starting at their junctions. The official distances of the railway are cleared, so the user
+    /// should be warned beforehand if `hasOfficialDistances` holds.
+    #[wasm_bindgen(js_name = reverseRailway)]
+    pub fn reverse_railway(mut self, rail_id: RailwayIndex) -> RerailMap {
+        self.railways[rail_id].reverse();
+        self.reindex_railway(rail_id);
+        self
+    }
+
+    /// Merges the station `b` into `a`: every point of `b` is linked to `a` instead, and `b` is
+    /// removed. The merged station keeps the name of `a` and takes the higher level of the two.
+    #[wasm_bindgen(js_name = mergeStations)]
+    pub fn merge_stations(mut self, a: StationIndex, b: StationIndex) -> RerailMap {
+        if a == b {
+            return self;
+        }
+
+        let station = &self[b];
+        let level = station.level;
+        let railways = station.railways.clone();
+        for &rail_id in &railways {
+            for pt in &mut self.railways[rail_id].points {
+                if pt.station == Some(b) {
+                    pt.station = Some(a);
+                }
+            }
+            self[a].add_railway(rail_id);
+        }
+        self[a].level = self[a].level.max(level);
+        self.stations.delete(b);
+        self
+    }
+
+    /// Moves the railways `railways` (given as railway ids) of the station to a new station with the
+    /// same name and level. Nothing is changed unless the station is left with at least one railway.
+    #[wasm_bindgen(js_name = splitStation)]
+    pub fn split_station(mut self, station_idx: StationIndex, railways: Vec<usize>) -> RerailMap {
+        let station = &self[station_idx];
+        let (moved, remaining): (Vec<_>, Vec<_>) = station
+            .railways
+            .iter()
+            .partition(|id| railways.contains(&id.as_usize()));
+        if moved.is_empty() || remaining.is_empty() {
+            return self;
+        }
+
+        let mut new_station = Station::new(station.name.clone(), station.level);
+        new_station.railways = moved.clone();
+        let new_station_idx = self.stations.push(new_station);
+        self[station_idx].railways = remaining;
+
+        for rail_id in moved {
+            for pt in &mut self.railways[rail_id].points {
+                if pt.station == Some(station_idx) {
+                    pt.station = Some(new_station_idx);
+                }
+            }
+        }
+        self
+    }
+
+    /// Welds the `j`-th point of the railway `rail_b` to the `i`-th point of `rail_a`, so that
+    /// they are kept at the same position from now on. The point of `rail_b`, together with the
+    /// points already welded to it, is moved to the point of `rail_a`.
+    #[wasm_bindgen(js_name = weldRailwayPoints)]
+    pub fn weld_railway_points(
+        mut self,
+        rail_a: RailwayIndex,
+        i: usize,
+        rail_b: RailwayIndex,
+        j: usize,
+    ) -> RerailMap {
+        if rail_a == rail_b && i == j {
+            return self;
+        }
+        let weld_a = self.railways[rail_a].points[i].weld;
+        let weld_b = self.railways[rail_b].points[j].weld;
+        if weld_a.is_some() && weld_a == weld_b {
+            return self;
+        }
+
+        let weld = match weld_a {
+            Some(weld) => weld,
+            None => {
+                let weld = self.new_weld_id();
+                self.railways[rail_a].points[i].weld = Some(weld);
+                weld
+            }
+        };
+        let moved = match weld_b {
+            Some(weld_b) => self.weld_members(weld_b),
+            None => vec![(rail_b, j)],
+        };
+        let coord = self.railways[rail_a].points[i].coord;
+        let mut rail_ids = vec![];
+        for (rail_id, k) in moved {
+            let pt = &mut self.railways[rail_id].points[k];
+            pt.weld = Some(weld);
+            pt.coord = coord;
+            rail_ids.push(rail_id);
+        }
+        // The new weld of `rail_a` is indexed first, so that it is never seen with only one point
+        self.reindex_railway(rail_a);
+        self.reindex_railways(rail_ids);
+
+        self
+    }
+
+    /// Welds both endpoints of the segment starting at the `j`-th point of `rail_b` to those of
+    /// the segment starting at the `i`-th point of `rail_a`, so that the railways share the track.
+    /// Endpoints are paired in the direction in which they are closer.
+    #[wasm_bindgen(js_name = weldRailwaySegments)]
+    pub fn weld_railway_segments(
+        self,
+        rail_a: RailwayIndex,
+        i: usize,
+        rail_b: RailwayIndex,
+        j: usize,
+    ) -> RerailMap {
+        let (a0, a1) = match self.railways[rail_a].segment_end(i) {
+            Some(i1) => (i, i1),
+            None => return self,
+        };
+        let (b0, b1) = match self.railways[rail_b].segment_end(j) {
+            Some(j1) => (j, j1),
+            None => return self,
+        };
+
+        let coord_a = |k: usize| self.railways[rail_a].points[k].coord;
+        let coord_b = |k: usize| self.railways[rail_b].points[k].coord;
+        let forward = distance_norm_square_points(coord_a(a0), coord_b(b0))
+            + distance_norm_square_points(coord_a(a1), coord_b(b1));
+        let backward = distance_norm_square_points(coord_a(a0), coord_b(b1))
+            + distance_norm_square_points(coord_a(a1), coord_b(b0));
+        let (b0, b1) = if forward <= backward {
+            (b0, b1)
+        } else {
+            (b1, b0)
+        };
+
+        self.weld_railway_points(rail_a, a0, rail_b, b0)
+            .weld_railway_points(rail_a, a1, rail_b, b1)
+    }
+
+    /// Detaches the `i`-th point of the railway from the points welded to it.
+    #[wasm_bindgen(js_name = unweldRailwayPoint)]
+    pub fn unweld_railway_point(mut self, rail_id: RailwayIndex, i: usize) -> RerailMap {
+        if self.railways[rail_id].points[i].weld.take().is_some() {
+            self.reindex_railway(rail_id);
+        }
+        self
+    }
+
+    /// Returns whether the `i`-th point of the railway is welded to other points.
+    #[wasm_bindgen(js_name = isRailwayPointWelded)]
+    pub fn is_railway_point_welded(&self, rail_id: RailwayIndex, i: usize) -> bool {
+        self.railways[rail_id].points[i].weld.is_some()
+    }
+
+    /// Segments of the railway which share the track with other railways, or with other branches
+    /// of the same railway. Segments are shared if both of their endpoints are welded to each other.
+    #[wasm_bindgen(js_name = sharedSegments)]
+    pub fn shared_segments(&self, rail_id: RailwayIndex) -> SharedSegments {
+        let groups = self.shared_segment_groups();
+        let railway = &self.railways[rail_id];
+
+        let mut segments = vec![];
+        for i in 0..railway.points.len() {
+            let key = match self.segment_weld_key(rail_id, i) {
+                Some(key) => key,
+                None => continue,
+            };
+            if let Some(group) = groups.get(&key) {
+                let mut railways = group
+                    .iter()
+                    .filter(|&&(r, k)| (r, k) != (rail_id, i))
+                    .map(|&(r, _)| r)
+                    .collect::<Vec<_>>();
+                railways.sort();
+                railways.dedup();
+                segments.push(SharedSegment { index: i, railways });
+            }
+        }
+
+        SharedSegments { segments }
+    }
+
+    /// Finds the route from the station `from` to `to` minimizing the distance along railways plus
+    /// `transfer_penalty` (in kilometers) for each change of railway at a station or a weld.
+    /// Distances are measured as in the station list, along the projection if the map has one. Returns `None` if `to` is not reachable, or if `transfer_penalty` is negative or not
+    /// finite.
+    #[wasm_bindgen(js_name = findRoute)]
+    pub fn find_route(
+        &self,
+        from: StationIndex,
+        to: StationIndex,
+        transfer_penalty: f64,
+    ) -> Option<Route> {
+        if !transfer_penalty.is_finite() || transfer_penalty < 0.0 {
+            return None;
+        }
+        let path = self.shortest_path(from, to, transfer_penalty)?;
+
+        let mut legs: Vec<RouteLeg> = vec![];
+        let mut distance = 0.0;
+        for (k, &(rail_id, i)) in path.iter().enumerate() {
+            let points = &self.railways[rail_id].points;
+            let continues = k > 0 && path[k - 1].0 == rail_id;
+            if continues {
+                let (_, h) = path[k - 1];
+                let leg = legs.last_mut().unwrap();
+                let d = self.kilometers_between(points[h].coord, points[i].coord);
+                leg.distance += d;
+                distance += d;
+                if leg.points.last() != Some(&i) {
+                    leg.points.push(i);
+                }
+            } else {
+                legs.push(RouteLeg {
+                    railway: rail_id,
+                    stations: vec![],
+                    points: vec![i],
+                    distance: 0.0,
+                });
+            }
+            let leg = legs.last_mut().unwrap();
+            if let Some(station_idx) = points[i].station {
+                if leg.stations.last() != Some(&station_idx) {
+                    leg.stations.push(station_idx);
+                }
+            }
+        }
+
+        // Legs only moving between the points of a station are not rides
+        legs.retain(|leg| leg.distance > 0.0);
+        let cost = distance + transfer_penalty * legs.len().saturating_sub(1) as f64;
+
+        Some(Route {
+            legs,
+            distance,
+            cost,
+        })
+    }
+
+    /// Analyzes how railways are connected through shared stations. Besides the connected
+    /// components and the articulation stations of the network, pairs of distinct stations within
+    /// `max_distance` in the logical coordinate, and each station paired with the nearest other
+    /// station of the same name, are reported as candidates for interchanges.
+    #[wasm_bindgen(js_name = analyzeConnectivity)]
+    pub fn analyze_connectivity(&self, max_distance: f64) -> ConnectivityReport {
+        // Vertices are railways followed by stations, and edges link stations to their railways
+        let rail_ids = self
+            .railways
+            .enumerate()
+            .map(|(rail_id, _)| rail_id)
+            .collect::<Vec<_>>();
+        let station_ids = self
+            .stations
+            .enumerate()
+            .map(|(station_idx, _)| station_idx)
+            .collect::<Vec<_>>();
+        let rail_vertex = rail_ids
+            .iter()
+            .enumerate()
+            .map(|(v, &rail_id)| (rail_id, v))
+            .collect::<HashMap<_, _>>();
+
+        let mut adj = vec![vec![]; rail_ids.len() + station_ids.len()];
+        for (k, &station_idx) in station_ids.iter().enumerate() {
+            let v = rail_ids.len() + k;
+            for rail_id in &self[station_idx].railways {
+                let w = rail_vertex[rail_id];
+                adj[v].push(w);
+                adj[w].push(v);
+            }
+        }
+
+        let component = connected_components(&adj);
+        let mut components = HashMap::<usize, NetworkComponent>::new();
+        for (v, &c) in component.iter().enumerate() {
+            let entry = components.entry(c).or_insert_with(|| NetworkComponent {
+                railways: vec![],
+                stations: vec![],
+            });
+            match rail_ids.get(v) {
+                Some(&rail_id) => entry.railways.push(rail_id),
+                None => entry.stations.push(station_ids[v - rail_ids.len()]),
+            }
+        }
+        let mut components = components.into_values().collect::<Vec<_>>();
+        components.sort_by(|a, b| {
+            b.railways
+                .len()
+                .cmp(&a.railways.len())
+                .then(a.railways.first().cmp(&b.railways.first()))
+        });
+        let isolated_railways = components
+            .iter()
+            .filter(|c| c.railways.len() == 1)
+            .map(|c| c.railways[0])
+            .collect();
+
+        let is_articulation = articulation_points(&adj);
+        let articulation_stations = station_ids
+            .iter()
+            .enumerate()
+            .filter(|&(k, _)| is_articulation[rail_ids.len() + k])
+            .map(|(_, &station_idx)| station_idx)
+            .collect();
+
+        ConnectivityReport {
+            components,
+            isolated_railways,
+            articulation_stations,
+            unlinked_station_pairs: self.unlinked_station_pairs(max_distance),
+        }
+    }
+
+    #[wasm_bindgen(js_name = getProjection)]
+    pub fn get_projection(&self) -> Option<Projection> {
+        self.projection
+    }
+
+    /// Sets the projection without moving any point, i.e. declares how the current coordinates
+    /// are related to the earth.
+    #[wasm_bindgen(js_name = setProjection)]
+    pub fn set_projection(mut self, projection: Projection) -> RerailMap {
+        self.projection = Some(projection);
+        self
+    }
+
+    #[wasm_bindgen(js_name = clearProjection)]
+    pub fn clear_projection(mut self) -> RerailMap {
+        self.projection = None;
+        self
+    }
+
+    /// Fits an affine projection to the control points, which should be three or more points not
+    /// on a line.
+    #[wasm_bindgen(js_name = fitAffineProjection)]
+    pub fn fit_affine_projection(control_points: ControlPoints) -> Option<Projection> {
+        Projection::fit_affine(&control_points.points)
+    }
+
+    #[wasm_bindgen(js_name = coordToGeo)]
+    pub fn coord_to_geo(&self, x: f64, y: f64) -> Option<GeoCoord> {
+        self.projection?.unproject((x, y))
+    }
+
+    #[wasm_bindgen(js_name = geoToCoord)]
+    pub fn geo_to_coord(&self, geo: GeoCoord) -> Option<Coord> {
+        let (x, y) = self.projection?.project(geo);
+        Some(Coord::new(x.round() as i32, y.round() as i32))
+    }
+
+    /// Distance in meters along the surface of the earth between two positions in the logical
+    /// coordinate.
+    #[wasm_bindgen(js_name = geodesicDistance)]
+    pub fn geodesic_distance(&self, a: Coord, b: Coord) -> Option<f64> {
+        let projection = self.projection?;
+        let a = projection.unproject((a.x as f64, a.y as f64))?;
+        let b = projection.unproject((b.x as f64, b.y as f64))?;
+        Some(geodesic_distance(a, b))
+    }
+
+    /// Moves all the points so that they keep their geographic positions under `projection`,
+    /// which becomes the projection of the map. If the map has no projection yet, or the current
+    /// one is degenerate, this is the same as `setProjection`. The map is left unchanged if some
+    /// point cannot be moved into the range of the logical coordinate.
+    pub fn reproject(mut self, projection: Projection) -> RerailMap {
+        let Some(current) = self.projection else {
+            return self.set_projection(projection);
+        };
+        if current.unproject((0.0, 0.0)).is_none() {
+            return self.set_projection(projection);
+        }
+        let convert = |coord: Coord| -> Option<Coord> {
+            let geo = current.unproject((coord.x as f64, coord.y as f64))?;
+            let (x, y) = projection.project(geo);
+            let (x, y) = (x.round(), y.round());
+            let range = i32::MIN as f64..=i32::MAX as f64;
+            (range.contains(&x) && range.contains(&y)).then(|| Coord::new(x as i32, y as i32))
+        };
+
+        // Every point is converted before any is moved. Points at the same position are converted
+        // to the same position, keeping welds and shared stations consistent
+        let railway_coords = self
+            .railways
+            .enumerate()
+            .map(|(rail_id, railway)| {
+                let coords = railway.points.iter().map(|pt| convert(pt.coord));
+                Some((rail_id, coords.collect::<Option<Vec<_>>>()?))
+            })
+            .collect::<Option<Vec<_>>>();
+        let border_coords = self
+            .border_points
+            .enumerate()
+            .map(|(id, pt)| Some((id, convert(pt.coord)?)))
+            .collect::<Option<Vec<_>>>();
+        let (Some(railway_coords), Some(border_coords)) = (railway_coords, border_coords) else {
+            return self;
+        };
+
+        for (rail_id, coords) in railway_coords {
+            for (pt, coord) in self.railways[rail_id].points.iter_mut().zip(coords) {
+                pt.coord = coord;
+            }
+        }
+        for (id, coord) in border_coords {
+            self.border_points[id].coord = coord;
+        }
+
+        self.projection = Some(projection);
+        self.rebuild_geometry_index();
+        self
+    }
+
+    /// Checks the consistency of the links between railways, stations, operators, welded points
+    /// and border points, and returns the descriptions of the problems found.
+    #[wasm_bindgen(js_name = checkIntegrity)]
+    pub fn check_integrity(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        for (rail_id, railway) in self.railways.enumerate() {
+            let n = railway.points.len();
+            if !railway.branch_starts.windows(2).all(|w| w[0] < w[1])
+                || railway.branch_starts.iter().any(|&s| s == 0 || s >= n)
+            {
+                problems.push(format!(
+                    "railway {}: invalid branch starts",
+                    rail_id.as_usize()
+                ));
+            }
+            if let Some(op) = railway.metadata.operator {
+                if self.operators.get(op).is_none() {
+                    problems.push(format!(
+                        "railway {}: refers to a removed operator {}",
+                        rail_id.as_usize(),
+                        op.as_usize()
+                    ));
+                }
+            }
+            for (i, pt) in railway.points.iter().enumerate() {
+                if let Some(station_idx) = pt.station {
+                    match self.stations.get(station_idx) {
+                        None => problems.push(format!(
+                            "railway {}: point {} refers to a removed station {}",
+                            rail_id.as_usize(),
+                            i,
+                            station_idx.as_usize()
+                        )),
+                        Some(station) if !station.railways.contains(&rail_id) => {
+                            problems.push(format!(
+                                "station {}: railway {} is missing",
+                                station_idx.as_usize(),
+                                rail_id.as_usize()
+                            ))
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        for (station_idx, station) in self.stations.enumerate() {
+            if station.railways.is_empty() {
+                problems.push(format!("station {}: no railway", station_idx.as_usize()));
+            }
+            for (k, &rail_id) in station.railways.iter().enumerate() {
+                if station.railways[..k].contains(&rail_id) {
+                    problems.push(format!(
+                        "station {}: railway {} is duplicated",
+                        station_idx.as_usize(),
+                        rail_id.as_usize()
+                    ));
+                }
+                let linked = self.railways.get(rail_id).is_some_and(|railway| {
+                    railway
+                        .points
+                        .iter()
+                        .any(|pt| pt.station == Some(station_idx))
+                });
+                if !linked {
+                    problems.push(format!(
+                        "station {}: railway {} does not pass the station",
+                        station_idx.as_usize(),
+                        rail_id.as_usize()
+                    ));
+                }
+            }
+        }
+
+        let mut weld_coords: HashMap<usize, Coord> = HashMap::new();
+        let mut weld_members: HashMap<usize, Vec<RailwayPointKey>> = HashMap::new();
+        for (rail_id, railway) in self.railways.enumerate() {
+            for (i, pt) in railway.points.iter().enumerate() {
+                let weld = match pt.weld {
+                    Some(weld) => weld,
+                    None => continue,
+                };
+                weld_members.entry(weld).or_default().push((rail_id, i));
+                if weld > self.weld_id_last {
+                    problems.push(format!(
+                        "railway {}: point {} has an unallocated weld {}",
+                        rail_id.as_usize(),
+                        i,
+                        weld
+                    ));
+                }
+                if *weld_coords.entry(weld).or_insert(pt.coord) != pt.coord {
+                    problems.push(format!(
+                        "weld {}: point {} of railway {} is apart from the others",
+                        weld,
+                        i,
+                        rail_id.as_usize()
+                    ));
+                }
+            }
+        }
+        for (&weld, members) in &weld_members {
+            if members.len() < 2 {
+                problems.push(format!("weld {}: only one point", weld));
+            }
+            let mut indexed = self.weld_members(weld);
+            indexed.sort();
+            if indexed != *members {
+                problems.push(format!("weld {}: index is out of date", weld));
+            }
+        }
+        for (&weld, members) in &self.geometry_index.welds {
+            if !members.is_empty() && !weld_members.contains_key(&weld) {
+                problems.push(format!("weld {}: index is out of date", weld));
+            }
+        }
+
+        for (i, pt) in self.border_points.enumerate() {
+            for &(j, level) in &pt.neighbors {
+                let symmetric = self
+                    .border_points
+                    .get(j)
+                    .is_some_and(|other| other.get_level(i) == Some(level));
+                if !symmetric {
+                    problems.push(format!(
+                        "border point {}: segment to {} is not symmetric",
+                        i.as_usize(),
+                        j.as_usize()
+                    ));
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// Searches stations and railways whose names match `query` exactly, as a prefix or as a
+    /// substring, ignoring the differences of full-width / half-width characters and hiragana /
+    /// katakana. Results are ranked by the kind of the match and then by level, and at most
+    /// `max_results` of them are returned.
+    pub fn search(&self, query: &str, max_results: usize) -> SearchResults {
+        let query = normalize(query.trim());
+        if query.is_empty() {
+            return SearchResults { results: vec![] };
+        }
+
+        let mut candidates = vec![];
+        for (id, station) in self.stations.enumerate() {
+            if let Some(kind) = match_name(&normalize(&station.name), &query) {
+                candidates.push((
+                    kind,
+                    SearchResultKind::Station,
+                    id.as_usize(),
+                    &station.name,
+                    station.level,
+                    bounding_box(self.station_coords(id)),
+                ));
+            }
+        }
+        for (id, railway) in self.railways.enumerate() {
+            if let Some(kind) = match_name(&normalize(&railway.name), &query) {
+                candidates.push((
+                    kind,
+                    SearchResultKind::Railway,
+                    id.as_usize(),
+                    &railway.name,
+                    railway.level,
+                    bounding_box(railway.points.iter().map(|pt| pt.coord)),
+                ));
+            }
+        }
+
+        candidates.sort_by(|a, b| {
+            (a.0, std::cmp::Reverse(a.4), a.3.len(), a.3).cmp(&(
+                b.0,
+                std::cmp::Reverse(b.4),
+                b.3.len(),
+                b.3,
+            ))
+        });
+
+        let results = candidates
+            .into_iter()
+            .filter_map(|(_, kind, id, name, level, bounds)| {
+                Some(SearchResult {
+                    kind,
+                    id,
+                    name: name.clone(),
+                    level,
+                    bounds: bounds?,
+                })
+            })
+            .take(max_results)
+            .collect();
+        SearchResults { results }
+    }
+
+    #[wasm_bindgen(js_name = railwayBounds)]
+    pub fn railway_bounds(&self, rail_id: RailwayIndex) -> Option<BoundingBox> {
+        let railway = self.railways.get(rail_id)?;
+        bounding_box(railway.points.iter().map(|pt| pt.coord))
+    }
+
+    /// Returns the bounding box of all the points linked to the station.
+    #[wasm_bindgen(js_name = stationBounds)]
+    pub fn station_bounds(&self, station_idx: StationIndex) -> Option<BoundingBox> {
+        self.stations.get(station_idx)?;
+        bounding_box(self.station_coords(station_idx))
+    }
+
+    /// Returns the bounding box of the railways and stations (given as ids) altogether.
+    /// Ids of removed entities are ignored.
+    #[wasm_bindgen(js_name = entitiesBounds)]
+    pub fn entities_bounds(
+        &self,
+        railways: Vec<usize>,
+        stations: Vec<usize>,
+    ) -> Option<BoundingBox> {
+        let railway_coords = railways
+            .into_iter()
+            .filter_map(|id| self.railways.get(RailwayIndex::from_usize(id)))
+            .flat_map(|railway| railway.points.iter().map(|pt| pt.coord));
+        let station_coords = stations
+            .into_iter()
+            .map(StationIndex::from_usize)
+            .filter(|&id| self.stations.get(id).is_some())
+            .flat_map(|id| self.station_coords(id));
+        bounding_box(railway_coords.chain(station_coords))
+    }
+
+    /// Returns the bounding box of all the railways and border points in the map.
+    #[wasm_bindgen(js_name = mapBounds)]
+    pub fn map_bounds(&self) -> Option<BoundingBox> {
+        let railway_coords = self
+            .railways
+            .iter()
+            .flat_map(|railway| railway.points.iter().map(|pt| pt.coord));
+        let border_coords = self.border_points.iter().map(|pt| pt.coord);
+        bounding_box(railway_coords.chain(border_coords))
+    }
+
+    /// Computes the viewport of a `width` x `height` canvas which shows `bounds` at the center,
+    /// leaving at least `margin` physical pixels around it.
+    #[wasm_bindgen(js_name = fitViewport)]
+    pub fn fit_viewport(bounds: BoundingBox, width: i32, height: i32, margin: i32) -> ViewportSpec {
+        // Computed in f64 since twice the margin may not fit in i32
+        let margin = margin.max(0) as f64;
+        let inner_width = (width as f64 - 2.0 * margin).max(1.0);
+        let inner_height = (height as f64 - 2.0 * margin).max(1.0);
+        let bounds_width = bounds.right as f64 - bounds.left as f64;
+        let bounds_height = bounds.bottom as f64 - bounds.top as f64;
+
+        let zoom = (bounds_width / inner_width).max(bounds_height / inner_height);
+        // A single point is shown at the scale of 1 logical unit per physical pixel
+        let zoom = if zoom > 0.0 { zoom } else { 1.0 };
+
+        let center_x = (bounds.left as f64 + bounds.right as f64) * 0.5;
+        let center_y = (bounds.top as f64 + bounds.bottom as f64) * 0.5;
+        ViewportSpec {
+            left_x: (center_x - width as f64 * zoom * 0.5).floor() as i32,
+            top_y: (center_y - height as f64 * zoom * 0.5).floor() as i32,
+            width,
+            height,
+            zoom,
+        }
+    }
+
+    /// Lists the stations on the railway. Distances are computed along the railway, restarting
+    /// from the official distance at each station which has one.
+    #[wasm_bindgen(js_name = stationListOnRailway)]
+    pub fn station_list_on_railway(&self, rail_id: RailwayIndex) -> StationListOnRailway {
+        let railway = &self.railways[rail_id];
+        let mut point_distances = vec![0.0f64; railway.points.len()];
+        let mut names = vec![];
+        let mut distances = vec![];
+        let mut branch_starts = vec![];
+        let mut points = vec![];
+        let mut official = vec![];
+
+        for branch in railway.branch_ranges() {
+            branch_starts.push(names.len());
+
+            // A branch is measured from the point where it leaves the preceding branches
+            let mut cur_distance = 0.0f64;
+            if branch.start > 0 {
+                if let Some(j) = railway.branch_origin(branch.start) {
+                    cur_distance = point_distances[j];
+                }
+            }
+
+            let wrap = branch.start == 0 && railway.is_ring();
+            let first_station = names.len();
+            // Distance along the branch from its start to the first station, regardless of
+            // official distances
+            let mut from_start = 0.0f64;
+            let mut first_station_offset = None;
+            let branch_end = branch.end;
+            for i in branch {
+                if i > 0 && railway.segment_end(i - 1) == Some(i) {
+                    let d = self
+                        .kilometers_between(railway.points[i - 1].coord, railway.points[i].coord);
+                    cur_distance += d;
+                    from_start += d;
+                }
+                let pt = &railway.points[i];
+                if let Some(id) = pt.station {
+                    first_station_offset.get_or_insert(from_start);
+                    if let Some(d) = pt.official_distance {
+                        cur_distance = d;
+                        official.push(names.len());
+                    }
+                    names.push(self[id].name.clone());
+                    distances.push(cur_distance);
+                    points.push(i);
+                }
+                point_distances[i] = cur_distance;
+            }
+
+            // The first station of a ring appears again at the end, one round later
+            if let Some(offset) = first_station_offset.filter(|_| wrap) {
+                cur_distance += self.kilometers_between(
+                    railway.points[branch_end - 1].coord,
+                    railway.points[0].coord,
+                );
+                names.push(names[first_station].clone());
+                distances.push(cur_distance + offset);
+                points.push(points[first_station]);
+            }
+        }
+
+        StationListOnRailway {
+            names,
+            distances,
+            branch_starts,
+            points,
+            official,
+        }
+    }
+
+    /// Returns whether any station on the railway has an official distance.
+    #[wasm_bindgen(js_name = hasOfficialDistances)]
+    pub fn has_official_distances(&self, rail_id: RailwayIndex) -> bool {
+        self.railways[rail_id].has_official_distances()
+    }
+
+    #[wasm_bindgen(js_name = getOfficialDistance)]
+    pub fn get_official_distance(&self, rail_id: RailwayIndex, index: usize) -> Option<f64> {
+        self.railways[rail_id].points[index].official_distance
+    }
+
+    /// Sets the official distance (in kilometers) of the station at the `index`-th point of the
+    /// railway, or clears it if `distance` is `None`. Nothing is changed if the point has no
+    /// station.
+    #[wasm_bindgen(js_name = setOfficialDistance)]
+    pub fn set_official_distance(
+        mut self,
+        rail_id: RailwayIndex,
+        index: usize,
+        distance: Option<f64>,
+    ) -> RerailMap {
+        let pt = &mut self.railways[rail_id].points[index];
+        if pt.station.is_some() {
+            pt.official_distance = distance.filter(|d| d.is_finite());
+        }
+        self
+    }
+
+    /// Exports the metadata of all the railways as CSV, one row per railway. Alternative names and
+    /// attributes (as `key=value`) are joined with `;`, and unknown values are left empty.
+    #[wasm_bindgen(js_name = exportRailwayTable)]
+    pub fn export_railway_table(&self) -> String {
+        let mut ret = String::from(
+            "railway,operator,line code,alternative names,gauge,electrification,tracks,attributes\n",
+        );
+        for (_, railway) in self.railways.enumerate() {
+            let metadata = &railway.metadata;
+            let operator = metadata
+                .operator
+                .and_then(|op| self.operators.get(op))
+                .map(|op| op.name.as_str())
+                .unwrap_or("");
+            let electrification = match metadata.electrification {
+                None => String::new(),
+                Some(Electrification::None) => "none".to_string(),
+                Some(Electrification::Dc { voltage }) => format!("DC {}V", voltage),
+                Some(Electrification::Ac { voltage, frequency }) => {
+                    format!("AC {}V {}Hz", voltage, frequency)
+                }
+            };
+            let attributes = metadata
+                .attributes
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>()
+                .join(";");
+            ret.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                csv_field(&railway.name),
+                csv_field(operator),
+                csv_field(metadata.line_code.as_deref().unwrap_or("")),
+                csv_field(&metadata.alternative_names.join(";")),
+                metadata.gauge.map(|g| g.to_string()).unwrap_or_default(),
+                electrification,
+                metadata
+                    .num_tracks
+                    .map(|n| n.to_string())
+                    .unwrap_or_default(),
+                csv_field(&attributes),
+            ));
+        }
+        ret
+    }
+
+    /// Exports the station lists of all the railways as CSV, with the columns of the railway name,
+    /// the branch number, the station name, the distance in kilometers and whether the distance is
+    /// official.
+    #[wasm_bindgen(js_name = exportDistanceTable)]
+    pub fn export_distance_table(&self) -> String {
+        let mut ret = String::from("railway,branch,station,distance,official\n");
+        for (rail_id, railway) in self.railways.enumerate() {
+            let list = self.station_list_on_railway(rail_id);
+            for (k, name) in list.names.iter().enumerate() {
+                let branch = list.branch_starts.partition_point(|&s| s <= k);
+                ret.push_str(&format!(
+                    "{},{},{},{:.3},{}\n",
+                    csv_field(&railway.name),
+                    branch,
+                    csv_field(name),
+                    list.distances[k],
+                    list.official.contains(&k),
+                ));
+            }
+        }
+        ret
+    }
+}
+
+impl RerailMap {
+    /// Describes the result of `analyze_connectivity` line by line with the names of the railways
+    /// and stations, for the command line.
+    pub fn connectivity_summary(&self, max_distance: f64) -> Vec<String> {
+        let report = self.analyze_connectivity(max_distance);
+        let railway_names = |rail_ids: &[RailwayIndex]| {
+            rail_ids
+                .iter()
+                .map(|&rail_id| self.railways[rail_id].name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let station_names = |station_ids: &[StationIndex]| {
+            station_ids
+                .iter()
+                .map(|&station_idx| self[station_idx].name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let mut ret = vec![format!("{} components", report.components.len())];
+        for (k, component) in report.components.iter().enumerate() {
+            ret.push(format!(
+                "  #{}: {} railways, {} stations: {}",
+                k + 1,
+                component.railways.len(),
+                component.stations.len(),
+                railway_names(&component.railways)
+            ));
+        }
+        ret.push(format!(
+            "Isolated railways: {}",
+            railway_names(&report.isolated_railways)
+        ));
+        ret.push(format!(
+            "Articulation stations: {}",
+            station_names(&report.articulation_stations)
+        ));
+        ret.push(format!(
+            "{} unlinked station pairs",
+            report.unlinked_station_pairs.len()
+        ));
+        for pair in &report.unlinked_station_pairs {
+            ret.push(format!(
+                "  {} - {}: {:.1}{}",
+                self[pair.a].name,
+                self[pair.b].name,
+                pair.distance,
+                if pair.same_name { " (same name)" } else { "" }
+            ));
+        }
+        ret
+    }
+
+    /// Drops the reference to an operator which does not exist.
+    fn validate_metadata(&self, mut metadata: RailwayMetadata) -> RailwayMetadata {
+        if metadata
+            .operator
+            .is_some_and(|op| self.operators.get(op).is_none())
+        {
+            metadata.operator = None;
+        }
+        metadata
+    }
+
+    /// Length of the segment between `a` and `b` in kilometers. Without a projection, one unit of
+    /// the logical coordinate is regarded as a meter.
+    fn kilometers_between(&self, a: Coord, b: Coord) -> f64 {
+        if let Some(d) = self.geodesic_distance(a, b) {
+            return d / 1000.0;
+        }
+        (distance_norm_square_points(a, b) as f64).sqrt() / 1000.0
+    }
+
+    /// Selects the entities in `rect` for which `contains` holds. Railways hidden in the viewport
+    /// are ignored.
+    fn select_by(
+        &self,
+        viewport: &Viewport,
+        rect: &Rect,
+        contains: impl Fn(Coord) -> bool,
+    ) -> Selection {
+        let mut railway_points = vec![];
+        for (rail_id, i) in self.geometry_index.railway_segments.query(rect) {
+            railway_points.push((rail_id, i));
+            railway_points.push((rail_id, self.railways[rail_id].segment_end(i).unwrap()));
+        }
+        railway_points.extend(self.geometry_index.station_points.query(rect));
+        // Railways consisting of a single point have no segments in the index
+        for (rail_id, railway) in self.railways.enumerate() {
+            if railway.points.len() == 1 {
+                railway_points.push((rail_id, 0));
+            }
+        }
+        railway_points.retain(|&(rail_id, i)| {
+            let railway = &self.railways[rail_id];
+            viewport.zoom <= RAILWAY_THRESHOLD[railway.level as usize] as f64
+                && contains(railway.points[i].coord)
+        });
+
+        let mut stations = railway_points
+            .iter()
+            .filter_map(|&(rail_id, i)| self.railways[rail_id].points[i].station)
+            .collect::<Vec<_>>();
+        stations.sort();
+        stations.dedup();
+        for &station_idx in &stations {
+            for &rail_id in &self[station_idx].railways {
+                for (i, pt) in self.railways[rail_id].points.iter().enumerate() {
+                    if pt.station == Some(station_idx) {
+                        railway_points.push((rail_id, i));
+                    }
+                }
+            }
+        }
+        railway_points.sort();
+        railway_points.dedup();
+
+        let mut border_points = self.geometry_index.border_points.query(rect);
+        border_points.retain(|&i| contains(self.border_points[i].coord));
+
+        Selection {
+            railway_points,
+            stations,
+            border_points,
+        }
+    }
+
+    /// Entities visible in `viewport` within `radius` physical pixels from `p`, ranked as in `pick`,
+    /// paired with the squared distance in the logical coordinate.
+    fn pick_hits(&self, viewport: &Viewport, p: Coord, radius: f64) -> Vec<(PickedEntity, f64)> {
+        let threshold = viewport.logical_distance_sq(radius);
+        let logical_radius = viewport.logical_radius(radius);
+        let railway_visible = |rail_id: RailwayIndex| {
+            viewport.zoom <= RAILWAY_THRESHOLD[self.railways[rail_id].level as usize] as f64
+        };
+
+        let mut hits = vec![];
+        let mut add_hit = |entity: PickedEntity, dist_sq: f64| {
+            if dist_sq <= threshold {
+                hits.push((entity, dist_sq));
+            }
+        };
+
+        for (rail_id, i) in self
+            .geometry_index
+            .railway_segments
+            .query_around(p, logical_radius)
+        {
+            if !railway_visible(rail_id) {
+                continue;
+            }
+            let railway = &self.railways[rail_id];
+            let j = railway.segment_end(i).unwrap();
+            add_hit(
+                PickedEntity::RailwaySegment(rail_id, i),
+                railway.distance_norm_square_to_segment(i, p) as f64,
+            );
+            for k in [i, j] {
+                if railway.points[k].station.is_none() {
+                    add_hit(
+                        PickedEntity::RailwayPoint(rail_id, k),
+                        distance_norm_square_points(railway.points[k].coord, p) as f64,
+                    );
+                }
+            }
+        }
+
+        for (rail_id, i) in self
+            .geometry_index
+            .station_points
+            .query_around(p, logical_radius)
+        {
+            if !railway_visible(rail_id) {
+                continue;
+            }
+            let railway = &self.railways[rail_id];
+            let station_idx = railway.points[i].station.unwrap();
+            let station_level = self[station_idx].level as usize;
+            if viewport.zoom > STATION_THRESHOLD[railway.level as usize][station_level] as f64 {
+                continue;
+            }
+            add_hit(
+                PickedEntity::Station(station_idx, rail_id, i),
+                distance_norm_square_points(railway.points[i].coord, p) as f64,
+            );
+        }
+
+        for i in self
+            .geometry_index
+            .border_points
+            .query_around(p, logical_radius)
+        {
+            add_hit(
+                PickedEntity::BorderPoint(i),
+                distance_norm_square_points(self.border_points[i].coord, p) as f64,
+            );
+        }
+
+        for (i, j) in self
+            .geometry_index
+            .border_segments
+            .query_around(p, logical_radius)
+        {
+            let c0 = self.border_points[i].coord;
+            let c1 = self.border_points[j].coord;
+            add_hit(
+                PickedEntity::BorderSegment(i, j),
+                distance_norm_square_point_line_segment(c0, c1, p) as f64,
+            );
+        }
+
+        // An endpoint shared by two segments is found twice
+        hits.sort_by_key(|a| a.0);
+        hits.dedup_by(|a, b| a.0 == b.0);
+        hits.sort_by(|a, b| {
+            let rank = |e: &PickedEntity| match e {
+                PickedEntity::Station(..) => 0,
+                PickedEntity::RailwayPoint(..) => 1,
+                PickedEntity::BorderPoint(..) => 2,
+                PickedEntity::RailwaySegment(..) => 3,
+                PickedEntity::BorderSegment(..) => 4,
+            };
+            (rank(&a.0), a.1).partial_cmp(&(rank(&b.0), b.1)).unwrap()
+        });
+
+        hits
+    }
+
+    /// The point from which the edited segment extends, used for snapping to 45 degrees.
+    fn previous_point_for_snap(&self, options: &SnapOptions) -> Option<Coord> {
+        if let Some(border_point) = options.border_point {
+            return Some(self.border_points[border_point].coord);
+        }
+
+        let railway = &self.railways[RailwayIndex::from_usize(options.rail_id?)];
+        let IndexOnRailway { index, inserting } = *options.editing_index.as_ref()?;
+        let prev = if inserting {
+            // A point inserted at the start of a branch extends the preceding one
+            index.saturating_sub(1)
+        } else if index > 0 && railway.segment_end(index - 1) == Some(index) {
+            index - 1
+        } else {
+            index + 1
+        };
+        railway.points.get(prev).map(|pt| pt.coord)
+    }
+
+    /// Railway points on the shortest path from a point of the station `from` to a point of `to`,
+    /// searched by Dijkstra's algorithm over the points of all railways. Segments weigh their length
+    /// in kilometers. Points of a railway at the same position are connected without cost, and
+    /// points of a station or a weld on different railways with `transfer_penalty` kilometers,
+    /// which must be non-negative.
+    fn shortest_path(
+        &self,
+        from: StationIndex,
+        to: StationIndex,
+        transfer_penalty: f64,
+    ) -> Option<Vec<(RailwayIndex, usize)>> {
+        // The index of the first node of each railway, and its nodes at the same positions
+        let mut railway_nodes = HashMap::<RailwayIndex, (usize, HashMap<Coord, Vec<usize>>)>::new();
+        let mut nodes = vec![];
+        let mut station_nodes = HashMap::<StationIndex, Vec<usize>>::new();
+        for (rail_id, railway) in self.railways.enumerate() {
+            let base = nodes.len();
+            let mut coincident = HashMap::<Coord, Vec<usize>>::new();
+            for (i, pt) in railway.points.iter().enumerate() {
+                if let Some(station_idx) = pt.station {
+                    station_nodes
+                        .entry(station_idx)
+                        .or_default()
+                        .push(nodes.len());
+                }
+                coincident.entry(pt.coord).or_default().push(nodes.len());
+                nodes.push((rail_id, i));
+            }
+            coincident.retain(|_, ids| ids.len() >= 2);
+            railway_nodes.insert(rail_id, (base, coincident));
+        }
+
+        let mut cost = vec![f64::INFINITY; nodes.len()];
+        let mut prev = vec![None; nodes.len()];
+        // Non-negative floats compare in the same order as their bit patterns
+        let mut queue = std::collections::BinaryHeap::new();
+        for &v in station_nodes.get(&from)? {
+            cost[v] = 0.0;
+            queue.push(std::cmp::Reverse((0u64, v)));
+        }
+
+        let mut goal = None;
+        while let Some(std::cmp::Reverse((c, v))) = queue.pop() {
+            let c = f64::from_bits(c);
+            if c > cost[v] {
+                continue;
+            }
+            let (rail_id, i) = nodes[v];
+            let railway = &self.railways[rail_id];
+            if railway.points[i].station == Some(to) {
+                goal = Some(v);
+                break;
+            }
+
+            let (base, coincident) = &railway_nodes[&rail_id];
+            let mut edges = vec![];
+            for j in [railway.segment_end(i), railway.segment_start(i)]
+                .into_iter()
+                .flatten()
+            {
+                let d = self.kilometers_between(railway.points[i].coord, railway.points[j].coord);
+                edges.push((base + j, d));
+            }
+            if let Some(ids) = coincident.get(&railway.points[i].coord) {
+                edges.extend(ids.iter().map(|&w| (w, 0.0)));
+            }
+            let penalty = |w: usize| {
+                if nodes[w].0 == rail_id {
+                    0.0
+                } else {
+                    transfer_penalty
+                }
+            };
+            if let Some(station_idx) = railway.points[i].station {
+                for &w in &station_nodes[&station_idx] {
+                    edges.push((w, penalty(w)));
+                }
+            }
+            if let Some(weld) = railway.points[i].weld {
+                for (r, k) in self.weld_members(weld) {
+                    let w = railway_nodes[&r].0 + k;
+                    edges.push((w, penalty(w)));
+                }
+            }
+
+            for (w, d) in edges {
+                if c + d < cost[w] {
+                    cost[w] = c + d;
+                    prev[w] = Some(v);
+                    queue.push(std::cmp::Reverse(((c + d).to_bits(), w)));
+                }
+            }
+        }
+
+        let mut path = vec![];
+        let mut v = goal?;
+        loop {
+            path.push(nodes[v]);
+            match prev[v] {
+                Some(u) => v = u,
+                None => break,
+            }
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// Pairs of distinct stations within `max_distance`, and pairs of each station with the nearest
+    /// other station of the same name, sorted by the distance.
+    fn unlinked_station_pairs(&self, max_distance: f64) -> Vec<UnlinkedStationPair> {
+        let mut station_coords = HashMap::<StationIndex, Vec<Coord>>::new();
+        for railway in self.railways.iter() {
+            for pt in &railway.points {
+                if let Some(station_idx) = pt.station {
+                    station_coords
+                        .entry(station_idx)
+                        .or_default()
+                        .push(pt.coord);
+                }
+            }
+        }
+        let station_distance = |a: StationIndex, b: StationIndex| {
+            let mut nearest = f64::INFINITY;
+            for &ca in &station_coords[&a] {
+                for &cb in &station_coords[&b] {
+                    nearest = nearest.min(distance_norm_square_points(ca, cb) as f64);
+                }
+            }
+            nearest.sqrt()
+        };
+
+        let mut pairs = HashMap::<(StationIndex, StationIndex), bool>::new();
+
+        let mut names = HashMap::<StationIndex, String>::new();
+        let mut by_name = HashMap::<String, Vec<(Coord, StationIndex)>>::new();
+        for (station_idx, station) in self.stations.enumerate() {
+            let name = normalize(station.name.trim());
+            if name.is_empty() {
+                continue;
+            }
+            let coords = station_coords.get(&station_idx).into_iter().flatten();
+            by_name
+                .entry(name.clone())
+                .or_default()
+                .extend(coords.map(|&c| (c, station_idx)));
+            names.insert(station_idx, name);
+        }
+        for points in by_name.values_mut() {
+            // Sweeps the points sorted by x for the nearest point of another station on each side
+            points.sort_by_key(|&(c, _)| (c.x, c.y));
+            let mut nearest = HashMap::<StationIndex, (i128, StationIndex)>::new();
+            for (k, &(c, a)) in points.iter().enumerate() {
+                let mut best = nearest.get(&a).map_or(i128::MAX, |&(d, _)| d);
+                let mut visit = |(other, b): (Coord, StationIndex)| {
+                    // Points further on this side are no nearer once they are too far along x
+                    if (other.x as i128 - c.x as i128).pow(2) >= best {
+                        return false;
+                    }
+                    let d = distance_norm_square_points(c, other);
+                    if b != a && d < best {
+                        best = d;
+                        nearest.insert(a, (d, b));
+                    }
+                    true
+                };
+                for &point in points[..k].iter().rev() {
+                    if !visit(point) {
+                        break;
+                    }
+                }
+                for &point in &points[k + 1..] {
+                    if !visit(point) {
+                        break;
+                    }
+                }
+            }
+            for (a, (_, b)) in nearest {
+                pairs.insert((a.min(b), a.max(b)), true);
+            }
+        }
+
+        let radius = max_distance.ceil().min(i32::MAX as f64) as i32;
+        for railway in self.railways.iter() {
+            for pt in &railway.points {
+                let a = match pt.station {
+                    Some(a) => a,
+                    None => continue,
+                };
+                for (other_rail, j) in self
+                    .geometry_index
+                    .station_points
+                    .query_around(pt.coord, radius)
+                {
+                    let other = &self.railways[other_rail].points[j];
+                    let b = other.station.unwrap();
+                    if a == b
+                        || distance_norm_square_points(pt.coord, other.coord) as f64
+                            > max_distance * max_distance
+                    {
+                        continue;
+                    }
+                    let same_name = names.contains_key(&a) && names.get(&a) == names.get(&b);
+                    pairs.entry((a.min(b), a.max(b))).or_insert(same_name);
+                }
+            }
+        }
+
+        let mut ret = pairs
+            .into_iter()
+            .map(|((a, b), same_name)| UnlinkedStationPair {
+                a,
+                b,
+                distance: station_distance(a, b),
+                same_name,
+            })
+            .collect::<Vec<_>>();
+        ret.sort_by(|x, y| {
+            (x.distance, x.a, x.b)
+                .partial_cmp(&(y.distance, y.a, y.b))
+                .unwrap()
+        });
+        ret
+    }
+
+    fn new_weld_id(&mut self) -> usize {
+        self.weld_id_last += 1;
+        self.weld_id_last
+    }
+
+    /// Welds the first point of every branch which is not welded yet to the first preceding point at
+    /// the same position. Branches in maps saved before junctions were welded are linked this way.
+    fn weld_branch_junctions(&mut self) {
+        let rail_ids = self
+            .railways
+            .enumerate()
+            .map(|(r, _)| r)
+            .collect::<Vec<_>>();
+        for rail_id in rail_ids {
+            for s in self.railways[rail_id].branch_starts.clone() {
+                let railway = &self.railways[rail_id];
+                let start = &railway.points[s];
+                if start.weld.is_some() {
+                    continue;
+                }
+                if let Some(j) = (0..s).find(|&j| railway.points[j].coord == start.coord) {
+                    let weld = match railway.points[j].weld {
+                        Some(weld) => weld,
+                        None => self.new_weld_id(),
+                    };
+                    let railway = &mut self.railways[rail_id];
+                    railway.points[j].weld = Some(weld);
+                    railway.points[s].weld = Some(weld);
+                }
+            }
+        }
+    }
+
+    /// Points welded with the weld id `weld`.
+    fn weld_members(&self, weld: usize) -> Vec<RailwayPointKey> {
+        self.geometry_index
+            .welds
+            .get(&weld)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The given points together with the points welded to them, sorted without duplicates.
+    fn with_welded_points(&self, points: &[RailwayPointKey]) -> Vec<RailwayPointKey> {
+        let mut ret = points.to_vec();
+        for &(rail_id, i) in points {
+            let weld = self
+                .railways
+                .get(rail_id)
+                .and_then(|railway| railway.points.get(i))
+                .and_then(|pt| pt.weld);
+            if let Some(weld) = weld {
+                ret.extend(self.weld_members(weld));
+            }
+        }
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    /// Moves the `i`-th point of the railway, together with the points welded to it, to `coord`.
+    fn move_welded_point(&mut self, rail_id: RailwayIndex, i: usize, coord: Coord) {
+        let mut rail_ids = vec![];
+        for (r, k) in self.with_welded_points(&[(rail_id, i)]) {
+            self.railways[r].points[k].coord = coord;
+            rail_ids.push(r);
+        }
+        self.reindex_railways(rail_ids);
+    }
+
+    /// The pair of the weld ids of the endpoints of the segment starting at the `i`-th point,
+    /// in the ascending order, if both endpoints are welded.
+    fn segment_weld_key(&self, rail_id: RailwayIndex, i: usize) -> Option<(usize, usize)> {
+        let railway = &self.railways[rail_id];
+        let j = railway.segment_end(i)?;
+        let wi = railway.points[i].weld?;
+        let wj = railway.points[j].weld?;
+        Some((wi.min(wj), wi.max(wj)))
+    }
+
+    /// Segments grouped by the welds of their endpoints. Only groups of two or more segments,
+    /// which are on the shared track, are returned.
+    fn shared_segment_groups(&self) -> HashMap<(usize, usize), Vec<(RailwayIndex, usize)>> {
+        let mut groups: HashMap<(usize, usize), Vec<(RailwayIndex, usize)>> = HashMap::new();
+        for (rail_id, railway) in self.railways.enumerate() {
+            for i in 0..railway.points.len() {
+                if let Some(key) = self.segment_weld_key(rail_id, i) {
+                    groups.entry(key).or_default().push((rail_id, i));
+                }
+            }
+        }
+        groups.retain(|_, group| group.len() >= 2);
+        groups
+    }
+
+    /// Coordinates of all the points linked to the station.
+    fn station_coords(&self, station_idx: StationIndex) -> impl Iterator<Item = Coord> + '_ {
+        self[station_idx].railways.iter().flat_map(move |&rail_id| {
+            self.railways[rail_id]
+                .points
+                .iter()
+                .filter(move |pt| pt.station == Some(station_idx))
+                .map(|pt| pt.coord)
+        })
+    }
+
+    pub(crate) fn rebuild_geometry_index(&mut self) {
+        self.geometry_index = GeometryIndex::default();
+
+        let rail_ids = self
+            .railways
+            .enumerate()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        for rail_id in rail_ids {
+            self.reindex_railway(rail_id);
+        }
+        // Release welds left with only one point
+        let lonely = self
+            .geometry_index
+            .welds
+            .iter()
+            .filter(|(_, members)| members.len() == 1)
+            .map(|(&weld, members)| (weld, members[0]))
+            .collect::<Vec<_>>();
+        for (weld, (rail_id, i)) in lonely {
+            self.railways[rail_id].points[i].weld = None;
+            self.geometry_index.welds.remove(&weld);
+        }
+
+        let border_point_ids = self
+            .border_points
+            .enumerate()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        for i in border_point_ids {
+            self.reindex_border_point(i);
+        }
+    }
+
+    /// Updates the geometry index for all the points and segments of the railway.
+    /// This should be called after the railway is modified, added or removed.
+    fn reindex_railway(&mut self, rail_id: RailwayIndex) {
+        let index = &mut self.geometry_index;
+
+        if let Some(n) = index.num_railway_points.remove(&rail_id) {
+            for i in 0..n {
+                index.railway_segments.remove((rail_id, i));
+                index.station_points.remove((rail_id, i));
+            }
+        }
+        let old_welds = index.railway_welds.remove(&rail_id).unwrap_or_default();
+        let mut old_sizes = vec![];
+        for &weld in &old_welds {
+            if let Some(members) = index.welds.get_mut(&weld) {
+                old_sizes.push((weld, members.len()));
+                members.retain(|&(r, _)| r != rail_id);
+            }
+        }
+
+        if let Some(railway) = self.railways.get(rail_id) {
+            let points = &railway.points;
+            for (i, point) in points.iter().enumerate() {
+                if railway.has_segment(i) {
+                    // Smooth railways are indexed by the bounding box of the curve
+                    let curve = railway.segment_curve(i);
+                    let lo = curve
+                        .iter()
+                        .fold(curve[0], |a, b| Coord::new(a.x.min(b.x), a.y.min(b.y)));
+                    let hi = curve
+                        .iter()
+                        .fold(curve[0], |a, b| Coord::new(a.x.max(b.x), a.y.max(b.y)));
+                    index.railway_segments.insert_segment((rail_id, i), lo, hi);
+                }
+                if point.station.is_some() {
+                    index.station_points.insert_point((rail_id, i), point.coord);
+                }
+                if let Some(weld) = point.weld {
+                    index.welds.entry(weld).or_default().push((rail_id, i));
+                    index.railway_welds.entry(rail_id).or_default().push(weld);
+                }
+            }
+            index.num_railway_points.insert(rail_id, points.len());
+            if let Some(welds) = index.railway_welds.get_mut(&rail_id) {
+                welds.sort();
+                welds.dedup();
+            }
+        }
+
+        // A weld which has lost all but one of its points no longer welds anything.
+        // The remaining point may be stale while other railways are being reindexed, so it is
+        // released only if it still has the weld.
+        for (weld, old_size) in old_sizes {
+            let members = match self.geometry_index.welds.get(&weld) {
+                Some(members) => members.clone(),
+                None => continue,
+            };
+            if members.is_empty() {
+                self.geometry_index.welds.remove(&weld);
+            } else if members.len() == 1 && old_size > 1 {
+                let (r, k) = members[0];
+                let pt = self
+                    .railways
+                    .get_mut(r)
+                    .and_then(|railway| railway.points.get_mut(k));
+                if let Some(pt) = pt.filter(|pt| pt.weld == Some(weld)) {
+                    pt.weld = None;
+                    self.geometry_index.welds.remove(&weld);
+                }
+            }
+        }
+    }
+
+    fn reindex_railways(&mut self, mut rail_ids: Vec<RailwayIndex>) {
+        rail_ids.sort();
+        rail_ids.dedup();
+        for rail_id in rail_ids {
+            self.reindex_railway(rail_id);
+        }
+    }
+
+    /// Updates the geometry index for the border point and all the segments incident to it.
+    /// Segments which are no longer present must be removed by `unindex_border_segment`.
+    fn reindex_border_point(&mut self, i: BorderPointIndex) {
+        let index = &mut self.geometry_index;
+
+        match self.border_points.get(i) {
+            Some(pt) => {
+                index.border_points.insert_point(i, pt.coord);
+                for &(j, _) in &pt.neighbors {
+                    index.border_segments.insert_segment(
+                        border_segment_key(i, j),
+                        pt.coord,
+                        self.border_points[j].coord,
+                    );
+                }
+            }
+            None => {
+                index.border_points.remove(i);
+            }
+        }
+    }
+
+    fn unindex_border_segment(&mut self, i: BorderPointIndex, j: BorderPointIndex) {
+        self.geometry_index
+            .border_segments
+            .remove(border_segment_key(i, j));
+    }
+}
+
+impl Default for RerailMap {
+    fn default() -> Self {
+        RerailMap::new()
+    }
+}
+
+impl Index<StationIndex> for RerailMap {
+    type Output = Station;
+
+    fn index(&self, index: StationIndex) -> &Self::Output {
+        &self.stations[index]
+    }
+}
+
+impl Index<RailwayIndex> for RerailMap {
+    type Output = Railway;
+
+    fn index(&self, index: RailwayIndex) -> &Self::Output {
+        &self.railways[index]
+    }
+}
+
+impl Index<BorderPointIndex> for RerailMap {
+    type Output = BorderPoint;
+
+    fn index(&self, index: BorderPointIndex) -> &Self::Output {
+        &self.border_points[index]
+    }
+}
+
+impl IndexMut<StationIndex> for RerailMap {
+    fn index_mut(&mut self, index: StationIndex) -> &mut Self::Output {
+        &mut self.stations[index]
+    }
+}
+
+impl IndexMut<RailwayIndex> for RerailMap {
+    fn index_mut(&mut self, index: RailwayIndex) -> &mut Self::Output {
+        &mut self.railways[index]
+    }
+}
+
+impl IndexMut<BorderPointIndex> for RerailMap {
+    fn index_mut(&mut self, index: BorderPointIndex) -> &mut Self::Output {
+        &mut self.border_points[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_integrity(map: &RerailMap) {
+        let problems = map.check_integrity();
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    fn add_railway(map: RerailMap, name: &str, points: &[(i32, i32)]) -> (RerailMap, RailwayIndex) {
+        let info = RailwayInfo {
+            name: name.to_string(),
+            level: 1,
+            color: 0,
+            metadata: RailwayMetadata::default(),
+        };
+        let result = map.new_railway_from_info(info, points[0].0, points[0].1);
+        let rail_id = result.index;
+        let mut map = result.map.unwrap();
+        for (i, &(x, y)) in points.iter().enumerate().skip(1) {
+            map = map.insert_railway_point(rail_id, i, x, y);
+        }
+        assert_integrity(&map);
+        (map, rail_id)
+    }
+
+    fn add_station(
+        map: RerailMap,
+        rail_id: RailwayIndex,
+        point_idx: usize,
+        name: &str,
+    ) -> RerailMap {
+        let info = StationInfo {
+            name: name.to_string(),
+            level: 0,
+        };
+        let map = map.set_station_info(rail_id, point_idx, info);
+        assert_integrity(&map);
+        map
+    }
+
+    fn coords(map: &RerailMap, rail_id: RailwayIndex) -> Vec<(i32, i32)> {
+        map[rail_id]
+            .points
+            .iter()
+            .map(|pt| (pt.coord.x, pt.coord.y))
+            .collect()
+    }
+
+    fn station_names(map: &RerailMap, rail_id: RailwayIndex) -> Vec<String> {
+        map.station_list_on_railway(rail_id).names
+    }
+
+    /// Distances in the station list in logical units, which are meters without a projection
+    fn station_distances(map: &RerailMap, rail_id: RailwayIndex) -> Vec<f64> {
+        map.station_list_on_railway(rail_id)
+            .distances
+            .iter()
+            .map(|d| (d * 1000.0).round())
+            .collect()
+    }
+
+    /// 1000 x 1000 pixels showing 10000 x 10000 units from the origin
+    fn viewport() -> ViewportSpec {
+        ViewportSpec {
+            left_x: 0,
+            top_y: 0,
+            width: 1000,
+            height: 1000,
+            zoom: 10.0,
+        }
+    }
+
+    fn rendering_options() -> RenderingOptions {
+        RenderingOptions {
+            selected_rail_id: None,
+            temporary_moving_point: None,
+            marker_on_border_points: false,
+            temporary_moving_border_point: None,
+            extra_border_segment: None,
+            temporary_moving_selection: None,
+            highlighted_route: None,
+            railway_filter: None,
+        }
+    }
+
+    #[test]
+    fn test_split_railway() {
+        let (map, a) = add_railway(
+            RerailMap::new(),
+            "a",
+            &[(0, 0), (100, 0), (200, 0), (300, 0), (400, 0)],
+        );
+        let map = add_station(map, a, 1, "s1");
+        let map = add_station(map, a, 2, "s2");
+        let map = add_station(map, a, 3, "s3");
+
+        // Nothing happens at the ends
+        let result = map.split_railway(a, 4);
+        assert!(result.index == a);
+        let map = result.map.unwrap();
+        assert_eq!(map[a].points.len(), 5);
+
+        let result = map.split_railway(a, 2);
+        let b = result.index;
+        let map = result.map.unwrap();
+        assert_integrity(&map);
+        assert_eq!(coords(&map, a), [(0, 0), (100, 0), (200, 0)]);
+        assert_eq!(coords(&map, b), [(200, 0), (300, 0), (400, 0)]);
+        assert_eq!(station_names(&map, a), ["s1", "s2"]);
+        assert_eq!(station_names(&map, b), ["s2", "s3"]);
+        let s2 = map[a].points[2].station.unwrap();
+        assert!(map[s2].railways == vec![a, b]);
+        assert!(map[b].points[0].station == Some(s2));
+    }
+
+    #[test]
+    fn test_merge_railways() {
+        let (map, a) = add_railway(
+            RerailMap::new(),
+            "a",
+            &[(0, 0), (100, 0), (200, 0), (300, 0), (400, 0)],
+        );
+        let map = add_station(map, a, 1, "s1");
+        let map = add_station(map, a, 2, "s2");
+        let map = add_station(map, a, 3, "s3");
+        let result = map.split_railway(a, 2);
+        let b = result.index;
+        let map = result.map.unwrap();
+
+        // The shared end points are unified
+        let map = map.merge_railways(b, a);
+        assert_integrity(&map);
+        assert!(map.railways.get(a).is_none());
+        assert_eq!(
+            coords(&map, b),
+            [(0, 0), (100, 0), (200, 0), (300, 0), (400, 0)]
+        );
+        assert_eq!(station_names(&map, b), ["s1", "s2", "s3"]);
+        let s2 = map[b].points[2].station.unwrap();
+        assert!(map[s2].railways == vec![b]);
+
+        // The closest ends are connected, reversing the other railway if needed
+        let (map, c) = add_railway(map, "c", &[(800, 0), (500, 0)]);
+        let map = add_station(map, c, 0, "s4");
+        let map = map.merge_railways(b, c);
+        assert_integrity(&map);
+        assert!(map.railways.get(c).is_none());
+        assert_eq!(
+            coords(&map, b),
+            [
+                (0, 0),
+                (100, 0),
+                (200, 0),
+                (300, 0),
+                (400, 0),
+                (500, 0),
+                (800, 0)
+            ]
+        );
+        assert_eq!(station_names(&map, b), ["s1", "s2", "s3", "s4"]);
+    }
+
+    #[test]
+    fn test_reverse_railway() {
+        let (map, a) = add_railway(RerailMap::new(), "a", &[(0, 0), (100, 0), (300, 0)]);
+        let map = add_station(map, a, 0, "s1");
+        let map = add_station(map, a, 1, "s2");
+        let map = map.reverse_railway(a);
+        assert_integrity(&map);
+        assert_eq!(coords(&map, a), [(300, 0), (100, 0), (0, 0)]);
+        assert_eq!(station_names(&map, a), ["s2", "s1"]);
+        assert_eq!(station_distances(&map, a), [200.0, 300.0]);
+
+        let map = map.reverse_railway(a);
+        assert_integrity(&map);
+        assert_eq!(coords(&map, a), [(0, 0), (100, 0), (300, 0)]);
+    }
+
+    #[test]
+    fn test_reverse_closed_railway() {
+        let (map, a) = add_railway(
+            RerailMap::new(),
+            "a",
+            &[(0, 0), (400, 0), (400, 300), (0, 300)],
+        );
+        let map = add_station(map, a, 0, "t");
+        let map = add_station(map, a, 1, "u");
+        let map = map.set_railway_closed(a, true);
+        assert_integrity(&map);
+        let list = map.station_list_on_railway(a);
+        assert_eq!(list.names, ["t", "u", "t"]);
+        assert_eq!(station_distances(&map, a), [0.0, 400.0, 1400.0]);
+
+        // The ring keeps its first point and all of its segments
+        let map = map.reverse_railway(a);
+        assert_integrity(&map);
+        assert!(map.is_railway_closed(a));
+        assert_eq!(coords(&map, a), [(0, 0), (0, 300), (400, 300), (400, 0)]);
+        let list = map.station_list_on_railway(a);
+        assert_eq!(list.names, ["t", "u", "t"]);
+        assert_eq!(station_distances(&map, a), [0.0, 1000.0, 1400.0]);
+        assert_eq!(map[a].segment_end(3), Some(0));
+    }
+
+    #[test]
+    fn test_reverse_branched_railway() {
+        let (map, a) = add_railway(RerailMap::new(), "a", &[(0, 0), (100, 0), (200, 0)]);
+        let map = add_station(map, a, 0, "s1");
+        let map = add_station(map, a, 1, "j");
+        let map = add_station(map, a, 2, "s2");
+        let map = map.add_railway_branch(a, 1);
+        let map = map.insert_railway_point(a, 4, 100, 300);
+        let map = add_station(map, a, 4, "b");
+        assert_eq!(station_names(&map, a), ["s1", "j", "s2", "j", "b"]);
+
+        // The first branch is reversed, and the spur still starts at the junction
+        let map = map.reverse_railway(a);
+        assert_integrity(&map);
+        assert_eq!(
+            coords(&map, a),
+            [(200, 0), (100, 0), (0, 0), (100, 0), (100, 300)]
+        );
+        assert_eq!(map[a].branch_starts, [3]);
+        let list = map.station_list_on_railway(a);
+        assert_eq!(list.names, ["s2", "j", "s1", "j", "b"]);
+        assert_eq!(
+            station_distances(&map, a),
+            [0.0, 100.0, 200.0, 100.0, 400.0]
+        );
+        assert_eq!(list.branch_starts, [0, 3]);
+    }
+
+    #[test]
+    fn test_railway_branch() {
+        let (map, a) = add_railway(RerailMap::new(), "a", &[(0, 0), (100, 0), (200, 0)]);
+        let map = add_station(map, a, 1, "j");
+        let map = add_station(map, a, 2, "s");
+        let map = map.add_railway_branch(a, 1);
+        assert_integrity(&map);
+        let map = map.insert_railway_point(a, 4, 100, 300);
+        let map = add_station(map, a, 4, "b");
+        assert_eq!(map[a].branch_starts, [3]);
+        assert_eq!(map[a].branch_origin(3), Some(1));
+
+        // The junction moves as one point
+        let map = map.move_railway_point(a, 1, 100, 100);
+        assert_integrity(&map);
+        assert_eq!(
+            coords(&map, a),
+            [(0, 0), (100, 100), (200, 0), (100, 100), (100, 300)]
+        );
+        let list = map.station_list_on_railway(a);
+        assert_eq!(list.names, ["j", "s", "j", "b"]);
+        assert_eq!(list.branch_starts, [0, 2]);
+        assert_eq!(station_distances(&map, a), [141.0, 283.0, 141.0, 341.0]);
+
+        // The junction station appears twice but is removed once
+        let (map, b) = add_railway(map, "b", &[(0, 500), (100, 500)]);
+        let map = add_station(map, b, 0, "t");
+        let map = map.remove_railway(a);
+        assert_integrity(&map);
+        assert_eq!(map.stations.iter().count(), 1);
+        assert_eq!(station_names(&map, b), ["t"]);
+    }
+
+    #[test]
+    fn test_split_and_merge_branched_railway() {
+        let (map, a) = add_railway(
+            RerailMap::new(),
+            "a",
+            &[(0, 0), (100, 0), (200, 0), (300, 0), (400, 0)],
+        );
+        let map = map.add_railway_branch(a, 1);
+        let map = map.insert_railway_point(a, 6, 100, 300);
+        let map = map.add_railway_branch(a, 3);
+        let map = map.insert_railway_point(a, 8, 300, 300);
+        let map = add_station(map, a, 6, "b1");
+        let mut map = add_station(map, a, 8, "b3");
+
+        // Nothing happens at the ends of branches
+        for i in [5, 6, 7, 8] {
+            let result = map.split_railway(a, i);
+            assert!(result.index == a);
+            map = result.map.unwrap();
+            assert_eq!(map[a].points.len(), 9);
+        }
+
+        // Each spur follows the part with its junction
+        let result = map.split_railway(a, 2);
+        let b = result.index;
+        let map = result.map.unwrap();
+        assert_integrity(&map);
+        assert_eq!(
+            coords(&map, a),
+            [(0, 0), (100, 0), (200, 0), (100, 0), (100, 300)]
+        );
+        assert_eq!(map[a].branch_starts, [3]);
+        assert_eq!(
+            coords(&map, b),
+            [(200, 0), (300, 0), (400, 0), (300, 0), (300, 300)]
+        );
+        assert_eq!(map[b].branch_starts, [3]);
+        assert_eq!(map[b].branch_origin(3), Some(1));
+        assert_eq!(station_names(&map, a), ["b1"]);
+        assert_eq!(station_names(&map, b), ["b3"]);
+
+        // The first branches are joined
+        let map = map.merge_railways(a, b);
+        assert_integrity(&map);
+        assert_eq!(
+            coords(&map, a),
+            [
+                (0, 0),
+                (100, 0),
+                (200, 0),
+                (300, 0),
+                (400, 0),
+                (100, 0),
+                (100, 300),
+                (300, 0),
+                (300, 300)
+            ]
+        );
+        assert_eq!(map[a].branch_starts, [5, 7]);
+        assert_eq!(station_distances(&map, a), [400.0, 600.0]);
+    }
+
+    #[test]
+    fn test_merge_and_split_stations() {
+        let (map, a) = add_railway(RerailMap::new(), "a", &[(100, 100), (500, 100)]);
+        let (map, b) = add_railway(map, "b", &[(110, 100), (110, 500)]);
+        let map = add_station(map, a, 0, "x");
+        let map = map.set_station_info(
+            b,
+            0,
+            StationInfo {
+                name: "y".to_string(),
+                level: 2,
+            },
+        );
+        let sa = map[a].points[0].station.unwrap();
+        let sb = map[b].points[0].station.unwrap();
+
+        let map = map.merge_stations(sa, sb);
+        assert_integrity(&map);
+        assert!(map.stations.get(sb).is_none());
+        assert!(map[b].points[0].station == Some(sa));
+        assert!(map[sa].railways == vec![a, b]);
+        assert_eq!(map[sa].name, "x");
+        assert_eq!(map[sa].level, 2);
+
+        // The railways given are moved to a new station with the same name
+        let map = map.split_station(sa, vec![b.as_usize()]);
+        assert_integrity(&map);
+        let sc = map[b].points[0].station.unwrap();
+        assert!(sc != sa);
+        assert_eq!(map[sc].name, "x");
+        assert!(map[sa].railways == vec![a]);
+        assert!(map[sc].railways == vec![b]);
+
+        // Nothing happens if all the railways would move
+        let map = map.split_station(sa, vec![a.as_usize()]);
+        assert_integrity(&map);
+        assert!(map[a].points[0].station == Some(sa));
+    }
+
+    #[test]
+    fn test_delete_selection() {
+        let (map, a) = add_railway(RerailMap::new(), "a", &[(0, 0), (100, 0), (200, 0)]);
+        let mut map = add_station(map, a, 1, "s");
+        let b = [(0, 100), (100, 100), (200, 100), (300, 100)]
+            .map(|(x, y)| map.add_border_point(BorderPoint::new(Coord::new(x, y))));
+        for k in 0..3 {
+            map.border_points[b[k]].add_neighbor(b[k + 1], 1);
+            map.border_points[b[k + 1]].add_neighbor(b[k], 1);
+        }
+        map.rebuild_geometry_index();
+
+        let selection = Selection {
+            railway_points: vec![(a, 1)],
+            stations: vec![],
+            border_points: vec![b[1]],
+        };
+        let map = map.delete_selection(selection);
+        assert_integrity(&map);
+        assert_eq!(coords(&map, a), [(0, 0), (200, 0)]);
+        assert!(map.stations.iter().next().is_none());
+        // The border point left without neighbors is removed too
+        assert!(map.border_points.get(b[0]).is_none());
+        assert!(map.border_points.get(b[1]).is_none());
+        assert_eq!(map.border_points[b[2]].get_level(b[3]), Some(1));
+    }
+
+    #[test]
+    fn test_link_to_station_candidate() {
+        let setup = || {
+            let (map, a) = add_railway(RerailMap::new(), "a", &[(0, 0), (100, 0)]);
+            let map = add_station(map, a, 1, "s");
+            let (map, b) = add_railway(map, "b", &[(110, 10), (110, 300)]);
+            let mut candidates = map
+                .station_candidates_for_link(
+                    b,
+                    0,
+                    viewport(),
+                    PhysicalCoord { x: 11.0, y: 1.0 },
+                    5.0,
+                )
+                .candidates;
+            assert_eq!(candidates.len(), 1);
+            (map, a, b, candidates.pop().unwrap())
+        };
+
+        // The position is taken from the station rather than the candidate
+        let (map, _, b, mut candidate) = setup();
+        let s = candidate.station;
+        candidate.x = 500;
+        let map = map.link_to_station_candidate(b, 0, candidate);
+        assert_integrity(&map);
+        assert_eq!(coords(&map, b)[0], (100, 0));
+        assert!(map[b].points[0].station == Some(s));
+
+        // A removed station is ignored
+        let (map, a, b, candidate) = setup();
+        let map = map.remove_railway(a);
+        let map = map.link_to_station_candidate(b, 0, candidate);
+        assert_integrity(&map);
+        assert_eq!(coords(&map, b)[0], (110, 10));
+        assert!(map[b].points[0].station.is_none());
+    }
+
+    #[test]
+    fn test_weld_railway_segments() {
+        let (map, a) = add_railway(RerailMap::new(), "a", &[(100, 100), (500, 100), (900, 100)]);
+        let (map, b) = add_railway(map, "b", &[(90, 900), (510, 110), (110, 90)]);
+
+        // The second segment of `b` runs backward along the first segment of `a`
+        let map = map.weld_railway_segments(a, 0, b, 1);
+        assert_integrity(&map);
+        assert_eq!(coords(&map, b), [(90, 900), (500, 100), (100, 100)]);
+        let shared = map.shared_segments(a).segments;
+        assert!(shared.len() == 1 && shared[0].index == 0 && shared[0].railways == vec![b]);
+        let shared = map.shared_segments(b).segments;
+        assert!(shared.len() == 1 && shared[0].index == 1 && shared[0].railways == vec![a]);
+
+        let map = map.move_railway_point(b, 1, 520, 200);
+        assert_integrity(&map);
+        assert_eq!(coords(&map, a)[1], (520, 200));
+
+        let selection = Selection {
+            railway_points: vec![(a, 0)],
+            stations: vec![],
+            border_points: vec![],
+        };
+        let map = map.translate_selection(selection, 5, 5);
+        assert_integrity(&map);
+        assert_eq!(coords(&map, b)[2], (105, 105));
+
+        let map = map.unweld_railway_point(a, 0);
+        assert_integrity(&map);
+        assert!(!map.is_railway_point_welded(a, 0));
+        assert!(!map.is_railway_point_welded(b, 2));
+        assert!(map.shared_segments(a).segments.is_empty());
+        let map = map.move_railway_point(a, 0, 0, 0);
+        assert_eq!(coords(&map, b)[2], (105, 105));
+    }
+
+    #[test]
+    fn test_welds_released_with_removed_points() {
+        let (map, a) = add_railway(RerailMap::new(), "a", &[(0, 0), (100, 0), (200, 0)]);
+        let (map, b) = add_railway(map, "b", &[(100, 100), (100, 10), (100, -100)]);
+        let (map, c) = add_railway(map, "c", &[(0, 100), (90, 0)]);
+        let map = map.weld_railway_points(a, 1, b, 1);
+        let map = map.weld_railway_points(a, 1, c, 1);
+        assert_integrity(&map);
+        assert_eq!(coords(&map, c)[1], (100, 0));
+
+        // Two points remain welded
+        let map = map.remove_railway(c);
+        assert_integrity(&map);
+        assert!(map.is_railway_point_welded(a, 1) && map.is_railway_point_welded(b, 1));
+
+        // The last point is released
+        let selection = Selection {
+            railway_points: vec![(b, 1)],
+            stations: vec![],
+            border_points: vec![],
+        };
+        let map = map.delete_selection(selection);
+        assert_integrity(&map);
+        assert!(!map.is_railway_point_welded(a, 1));
+        let map = map.move_railway_point(a, 1, 100, 50);
+        assert_integrity(&map);
+        assert_eq!(coords(&map, b), [(100, 100), (100, -100)]);
+    }
+
+    #[test]
+    fn test_render_moving_welded_point() {
+        let (map, a) = add_railway(RerailMap::new(), "a", &[(0, 0), (1000, 0)]);
+        let (map, b) = add_railway(map, "b", &[(1000, 0), (1000, 1000)]);
+        let map = map.weld_railway_points(a, 1, b, 0);
+
+        // The welded point of `b` follows the point of `a` being dragged
+        let mut opts = rendering_options();
+        opts.selected_rail_id = Some(a.as_usize());
+        opts.temporary_moving_point = Some(TemporaryMovingPoint {
+            index: IndexOnRailway {
+                index: 1,
+                inserting: false,
+            },
+            point_after_move: PhysicalCoord { x: 50.0, y: 50.0 },
+        });
+        let info = map.render(viewport(), opts);
+        let points = info
+            .rail_points_x
+            .iter()
+            .zip(info.rail_points_y.iter())
+            .map(|(&x, &y)| (x, y))
+            .collect::<Vec<_>>();
+        assert!(points.contains(&(50.0, 50.0)));
+        assert!(!points.contains(&(100.0, 0.0)));
+
+        // So do the welded points of a selection being moved
+        let mut opts = rendering_options();
+        opts.temporary_moving_selection = Some(TemporaryMovingSelection {
+            selection: Selection {
+                railway_points: vec![(b, 0)],
+                stations: vec![],
+                border_points: vec![],
+            },
+            offset: PhysicalCoord { x: 0.0, y: 10.0 },
+        });
+        let info = map.render(viewport(), opts);
+        assert_eq!(info.rail_points_y.iter().filter(|&&y| y == 10.0).count(), 2);
+    }
+
+    #[test]
+    fn test_render_parallel_railways() {
+        // `b` runs backward on the track of `a`, which turns so that the direction of the second
+        // segment is reversed in the order of coordinates
+        let (map, a) = add_railway(
+            RerailMap::new(),
+            "a",
+            &[(1000, 1000), (2000, 1000), (1000, 2000)],
+        );
+        let (map, b) = add_railway(map, "b", &[(1000, 2000), (2000, 1000), (1000, 1000)]);
+        let map = map.weld_railway_segments(a, 0, b, 1);
+        let map = map.weld_railway_segments(a, 1, b, 0);
+        let map = add_station(map, a, 0, "s");
+        let map = add_station(map, b, 2, "t");
+        let s = map[a].points[0].station.unwrap();
+        let t = map[b].points[2].station.unwrap();
+        let map = map.merge_stations(s, t);
+        assert_integrity(&map);
+
+        let info = map.render(viewport(), rendering_options());
+        // Two railways and two ticks side by side at the station
+        assert_eq!(info.polyline_points_num.len(), 4);
+        let n = info.polyline_points_num[0] as usize;
+        assert_eq!(n, 3);
+        // The offset of `a` is `o * (0, 1)` on the first segment and `o * (-1, -1) / sqrt(2)` on
+        // the second one with the same `o`
+        let first = (info.rail_points_x[0] - 100.0, info.rail_points_y[0] - 100.0);
+        let last = (
+            info.rail_points_x[n - 1] - 100.0,
+            info.rail_points_y[n - 1] - 200.0,
+        );
+        assert!(first.0.abs() < 1e-9 && first.1 != 0.0);
+        assert!(last.0 != 0.0 && first.1.signum() == -last.0.signum());
+        // `b` is on the other side
+        let m = info.polyline_points_num[1] as usize;
+        let b_last = info.rail_points_y[n + m - 1] - 100.0;
+        assert!((b_last + first.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_pick_smooth_railway() {
+        let map = RerailMap::new();
+        let (map, a) = add_railway(
+            map,
+            "A",
+            &[(1000, 1000), (2000, 3000), (3000, 3000), (4000, 1000)],
+        );
+        let map = map.set_railway_smooth(a, true);
+        assert_integrity(&map);
+
+        // The middle segment bulges below its chord
+        let curve = map.railways[a].segment_curve(1);
+        let mid = curve[curve.len() / 2];
+        assert!(mid.y > 3100);
+
+        let hits = |map: &RerailMap, c: Coord| {
+            map.pick(viewport(), c.x as f64 / 10.0, c.y as f64 / 10.0, 5.0)
+                .hits
+                .into_iter()
+                .map(|hit| hit.entity)
+                .collect::<Vec<_>>()
+        };
+        let segment = PickedEntity::RailwaySegment(a, 1);
+        assert!(hits(&map, mid).contains(&segment));
+        assert!(!hits(&map, Coord::new(2500, 3000)).contains(&segment));
+
+        let map = map.set_railway_smooth(a, false);
+        assert_integrity(&map);
+        assert!(!hits(&map, mid).contains(&segment));
+        assert!(hits(&map, Coord::new(2500, 3000)).contains(&segment));
+    }
+
+    #[test]
+    fn test_find_route() {
+        let (map, a) = add_railway(RerailMap::new(), "a", &[(100, 100), (500, 100), (900, 100)]);
+        let map = add_station(map, a, 0, "s1");
+        let map = add_station(map, a, 1, "s2");
+        let map = add_station(map, a, 2, "s3");
+        let (map, b) = add_railway(map, "b", &[(520, 100), (500, 500)]);
+        let map = map.link_to_station(b, 0, viewport(), PhysicalCoord { x: 51.0, y: 10.0 }, 5.0);
+        let map = add_station(map, b, 1, "s4");
+        let (map, c) = add_railway(map, "c", &[(110, 100), (100, 900), (500, 900), (500, 510)]);
+        let map = map.link_to_station(c, 0, viewport(), PhysicalCoord { x: 11.0, y: 10.0 }, 5.0);
+        let map = map.link_to_station(c, 3, viewport(), PhysicalCoord { x: 50.0, y: 51.0 }, 5.0);
+        let (map, d) = add_railway(map, "d", &[(800, 800), (900, 800)]);
+        let map = add_station(map, d, 1, "s5");
+        assert_integrity(&map);
+        let station = |map: &RerailMap, rail_id: RailwayIndex, i: usize| {
+            map[rail_id].points[i].station.unwrap()
+        };
+        let (s1, s2, s3) = (
+            station(&map, a, 0),
+            station(&map, a, 1),
+            station(&map, a, 2),
+        );
+        let (s4, s5) = (station(&map, b, 1), station(&map, d, 1));
+        assert!(station(&map, c, 0) == s1 && station(&map, c, 3) == s4);
+
+        // Transferring at s2 is shorter unless the penalty is large
+        let route = map.find_route(s1, s4, 0.0).unwrap();
+        assert_eq!(route.legs.len(), 2);
+        assert!(route.legs[0].railway == a && route.legs[0].stations == vec![s1, s2]);
+        assert_eq!(route.legs[0].points, vec![0, 1]);
+        assert!(route.legs[1].railway == b && route.legs[1].stations == vec![s2, s4]);
+        assert!((route.distance - 0.8).abs() < 1e-9 && (route.cost - 0.8).abs() < 1e-9);
+        let route = map.find_route(s1, s4, 1.0).unwrap();
+        assert!(route.legs.len() == 1 && route.legs[0].railway == c);
+        assert!((route.distance - 1.6).abs() < 1e-9);
+
+        let route = map.find_route(s3, s1, 1.0).unwrap();
+        assert!(route.legs.len() == 1 && route.legs[0].stations == vec![s3, s2, s1]);
+        assert_eq!(route.legs[0].points, vec![2, 1, 0]);
+        let route = map.find_route(s1, s1, 1.0).unwrap();
+        assert!(route.legs.is_empty() && route.distance == 0.0);
+        assert!(map.find_route(s1, s5, 0.0).is_none());
+
+        // Welded points connect railways without a station
+        let map = map.weld_railway_points(c, 2, d, 0);
+        assert_integrity(&map);
+        let route = map.find_route(s1, s5, 1.0).unwrap();
+        assert_eq!(route.legs.len(), 2);
+        assert!(route.legs[0].railway == c && route.legs[1].railway == d);
+        assert!(route.legs[1].stations == vec![s5]);
+        assert!((route.cost - route.distance - 1.0).abs() < 1e-9);
+
+        for penalty in [-1.0, f64::NAN, f64::INFINITY] {
+            assert!(map.find_route(s1, s4, penalty).is_none());
+        }
+
+        // Lengths follow the projection as in the station list, which changes the best route
+        let origin = GeoCoord { lat: 0.0, lon: 0.0 };
+        let map = map.set_projection(Projection::Equirectangular { origin, scale: 0.5 });
+        let route = map.find_route(s1, s4, 1.0).unwrap();
+        assert!(route.legs.len() == 2 && route.legs[0].railway == a);
+        assert!((route.distance - 1.6).abs() < 1e-6 && (route.cost - 2.6).abs() < 1e-6);
+        assert_eq!(
+            station_distances(&map, a)[1],
+            (route.legs[0].distance * 1000.0).round()
+        );
+    }
+
+    #[test]
+    fn test_analyze_connectivity() {
+        let (map, a) = add_railway(RerailMap::new(), "a", &[(100, 100), (500, 100)]);
+        let map = add_station(map, a, 0, "s1");
+        let map = add_station(map, a, 1, "s2");
+        let (map, b) = add_railway(map, "b", &[(520, 100), (500, 500)]);
+        let map = map.link_to_station(b, 0, viewport(), PhysicalCoord { x: 51.0, y: 10.0 }, 5.0);
+        let map = add_station(map, b, 1, "ｓ３");
+        let (map, c) = add_railway(map, "c", &[(505, 505), (900, 900)]);
+        let map = add_station(map, c, 0, "s4");
+        let map = add_station(map, c, 1, "s3");
+        let (map, d) = add_railway(map, "d", &[(3000, 3000), (3000, 4000)]);
+        let map = add_station(map, d, 0, "s3");
+        let s2 = map[a].points[1].station.unwrap();
+        let s3b = map[b].points[1].station.unwrap();
+        let (s4, s3c) = (
+            map[c].points[0].station.unwrap(),
+            map[c].points[1].station.unwrap(),
+        );
+        let s3d = map[d].points[0].station.unwrap();
+
+        let report = map.analyze_connectivity(10.0);
+        assert_eq!(report.components.len(), 3);
+        assert!(report.components[0].railways == vec![a, b]);
+        assert_eq!(report.components[0].stations.len(), 3);
+        assert!(report.isolated_railways == vec![c, d]);
+        assert!(report.articulation_stations == vec![s2]);
+
+        // Stations of the same name are paired only with the nearest one
+        let pairs = report
+            .unlinked_station_pairs
+            .iter()
+            .map(|p| (p.a.min(p.b), p.a.max(p.b), p.same_name))
+            .collect::<Vec<_>>();
+        assert_eq!(pairs.len(), 3);
+        assert!(pairs[0] == (s3b.min(s4), s3b.max(s4), false));
+        assert!(pairs[1] == (s3b.min(s3c), s3b.max(s3c), true));
+        assert!(pairs[2] == (s3c.min(s3d), s3c.max(s3d), true));
+        let distance = report.unlinked_station_pairs[0].distance;
+        assert!((distance - 50f64.sqrt()).abs() < 1e-9);
+
+        let map = map.merge_stations(s3b, s4);
+        assert_integrity(&map);
+        let report = map.analyze_connectivity(10.0);
+        assert_eq!(report.components.len(), 2);
+        assert!(report.components[0].railways == vec![a, b, c]);
+        assert!(report.isolated_railways == vec![d]);
+        assert_eq!(report.articulation_stations.len(), 2);
+        let summary = map.connectivity_summary(10.0);
+        assert_eq!(summary[0], "2 components");
+        assert!(summary.iter().any(|line| line == "Isolated railways: d"));
+    }
+
+    #[test]
+    fn test_reproject() {
+        let (map, a) = add_railway(RerailMap::new(), "a", &[(1000, 1000), (3000, 1000)]);
+        let map = add_station(map, a, 1, "s");
+        let (map, b) = add_railway(map, "b", &[(3010, 1010), (1500, 2500)]);
+        let map = map.link_to_station(b, 0, viewport(), PhysicalCoord { x: 301.0, y: 101.0 }, 5.0);
+        let map = map.weld_railway_points(a, 0, b, 1);
+        assert_integrity(&map);
+        assert!(coords(&map, b) == vec![(3000, 1000), (1000, 1000)]);
+
+        let origin = GeoCoord {
+            lat: 35.0,
+            lon: 139.0,
+        };
+        let map = map.reproject(Projection::Equirectangular { origin, scale: 0.1 });
+        assert!(coords(&map, a) == vec![(1000, 1000), (3000, 1000)]);
+        let geo = map.coord_to_geo(1000.0, 1000.0).unwrap();
+        assert!(geo.lat < 35.0 && geo.lon > 139.0);
+
+        // Welded points and the points of a station stay at the same position
+        let map = map.reproject(Projection::WebMercator { origin, scale: 0.3 });
+        assert_integrity(&map);
+        let (ca, cb) = (coords(&map, a), coords(&map, b));
+        assert!(ca[0] == cb[1] && ca[1] == cb[0]);
+        assert!(ca != vec![(1000, 1000), (3000, 1000)]);
+        assert!(map.geo_to_coord(geo) == Some(Coord::new(ca[0].0, ca[0].1)));
+
+        // Nothing is moved if some point goes out of the range
+        let mut map = map;
+        for scale in [1e9, f64::NAN] {
+            map = map.reproject(Projection::WebMercator { origin, scale });
+            assert_integrity(&map);
+            assert!(coords(&map, a) == ca && coords(&map, b) == cb);
+            assert!(map.projection == Some(Projection::WebMercator { origin, scale: 0.3 }));
+        }
+    }
+
+    #[test]
+    fn test_official_distances() {
+        let points = [(1000, 1000), (4000, 1000), (6000, 1000), (7000, 1000)];
+        let (map, a) = add_railway(RerailMap::new(), "A,B", &points);
+        let map = add_station(map, a, 0, "S0");
+        let map = add_station(map, a, 1, "S1");
+        let map = add_station(map, a, 2, "S2");
+        assert_eq!(station_distances(&map, a), vec![0.0, 3000.0, 5000.0]);
+        assert!(!map.has_official_distances(a));
+
+        // Distances restart from the official one, which only a station can have
+        let map = map.set_official_distance(a, 1, Some(3.4));
+        let map = map.set_official_distance(a, 3, Some(1.0));
+        assert_integrity(&map);
+        assert_eq!(map.get_official_distance(a, 3), None);
+        assert!(map.has_official_distances(a));
+        assert_eq!(station_distances(&map, a), vec![0.0, 3400.0, 5400.0]);
+        let list = map.station_list_on_railway(a);
+        assert_eq!(list.official, vec![1]);
+        assert_eq!(list.points, vec![0, 1, 2]);
+        assert_eq!(
+            map.export_distance_table(),
+            "railway,branch,station,distance,official\n\
+             \"A,B\",1,S0,0.000,false\n\
+             \"A,B\",1,S1,3.400,true\n\
+             \"A,B\",1,S2,5.400,false\n"
+        );
+
+        // Reversing clears the official distances measured from the old start
+        let map = map.reverse_railway(a);
+        assert_integrity(&map);
+        assert!(!map.has_official_distances(a));
+        assert_eq!(station_names(&map, a), vec!["S2", "S1", "S0"]);
+        assert_eq!(station_distances(&map, a), vec![1000.0, 3000.0, 6000.0]);
+
+        // The first station of a ring appears again one round later
+        let points = [(1000, 3000), (2000, 3000), (2000, 4000), (1000, 4000)];
+        let (map, r) = add_railway(map, "R", &points);
+        let map = add_station(map, r, 1, "P");
+        let map = add_station(map, r, 2, "Q");
+        let map = map.set_railway_closed(r, true);
+        assert_integrity(&map);
+        assert_eq!(station_names(&map, r), vec!["P", "Q", "P"]);
+        assert_eq!(station_distances(&map, r), vec![1000.0, 2000.0, 5000.0]);
+        let map = map.set_official_distance(r, 1, Some(10.0));
+        assert_eq!(station_distances(&map, r), vec![10000.0, 11000.0, 14000.0]);
+
+        // Prepending a railway clears the official distances measured from the old start
+        let (map, x) = add_railway(map, "X", &[(3000, 6000), (5000, 6000)]);
+        let map = add_station(map, x, 0, "X0");
+        let map = add_station(map, x, 1, "X1");
+        let map = map.set_official_distance(x, 1, Some(5.0));
+        let (map, y) = add_railway(map, "Y", &[(1000, 6000), (2000, 6000), (3000, 6000)]);
+        let map = add_station(map, y, 1, "Y");
+        let map = map.set_official_distance(y, 1, Some(1.5));
+        let map = map.merge_railways(x, y);
+        assert_integrity(&map);
+        assert_eq!(station_names(&map, x), vec!["Y", "X0", "X1"]);
+        assert_eq!(map.station_list_on_railway(x).official, vec![0]);
+        assert_eq!(station_distances(&map, x), vec![1500.0, 2500.0, 4500.0]);
+    }
+
+    #[test]
+    fn test_railway_filter() {
+        let op = OperatorIndex::from_usize(1);
+        let metadata = RailwayMetadata {
+            operator: Some(op),
+            gauge: Some(1067),
+            electrification: Some(Electrification::Dc { voltage: 1500 }),
+            num_tracks: Some(2),
+            attributes: vec![("kind".to_string(), "loop".to_string())],
+            ..Default::default()
+        };
+        let unknown = RailwayMetadata::default();
+
+        let filter = RailwayFilter::default();
+        assert!(filter.matches(&metadata) && filter.matches(&unknown));
+
+        let filters = [
+            RailwayFilter {
+                operators: Some(vec![OperatorIndex::from_usize(0), op]),
+                ..Default::default()
+            },
+            RailwayFilter {
+                gauges: Some(vec![1067, 1435]),
+                ..Default::default()
+            },
+            RailwayFilter {
+                electrified: Some(true),
+                ..Default::default()
+            },
+            RailwayFilter {
+                min_tracks: Some(2),
+                ..Default::default()
+            },
+            RailwayFilter {
+                attributes: vec![("kind".to_string(), "loop".to_string())],
+                ..Default::default()
+            },
+        ];
+        // Unknown metadata matches no condition
+        for filter in filters {
+            assert!(filter.matches(&metadata) && !filter.matches(&unknown));
+        }
+
+        let filters = [
+            RailwayFilter {
+                operators: Some(vec![]),
+                ..Default::default()
+            },
+            RailwayFilter {
+                gauges: Some(vec![1435]),
+                ..Default::default()
+            },
+            RailwayFilter {
+                electrified: Some(false),
+                ..Default::default()
+            },
+            RailwayFilter {
+                min_tracks: Some(4),
+                ..Default::default()
+            },
+            RailwayFilter {
+                attributes: vec![("kind".to_string(), "branch".to_string())],
+                ..Default::default()
+            },
+            RailwayFilter {
+                gauges: Some(vec![1067]),
+                min_tracks: Some(3),
+                ..Default::default()
+            },
+        ];
+        for filter in filters {
+            assert!(!filter.matches(&metadata));
+        }
+
+        let not_electrified = RailwayMetadata {
+            electrification: Some(Electrification::None),
+            ..Default::default()
+        };
+        let filter = RailwayFilter {
+            electrified: Some(false),
+            ..Default::default()
+        };
+        assert!(filter.matches(&not_electrified));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let (map, a) = add_railway(RerailMap::new(), "A", &[(100, 100), (1000, 100)]);
+        let (map, b) = add_railway(map, "B", &[(100, 500), (1000, 500)]);
+        let map = add_station(map, a, 1, "S");
+        let map = map.set_official_distance(a, 1, Some(1.5));
+        let map = map.weld_railway_points(a, 0, b, 0);
+
+        // The removed operator leaves a gap in the indices
+        let mut result = map.add_operator("X".to_string());
+        let removed = result.get_operator_index();
+        let mut result = result.get_map().add_operator("JR".to_string());
+        let op = result.get_operator_index();
+        let map = result.get_map().remove_operator(removed);
+        let mut info = map.get_railway_info(a);
+        info.metadata = RailwayMetadata {
+            operator: Some(op),
+            line_code: Some("JY".to_string()),
+            alternative_names: vec!["Loop".to_string()],
+            gauge: Some(1067),
+            electrification: Some(Electrification::Ac {
+                voltage: 20000,
+                frequency: 50,
+            }),
+            num_tracks: Some(2),
+            attributes: vec![("kind".to_string(), "loop, line".to_string())],
+        };
+        let map = map.set_railway_info(a, info);
+        assert_integrity(&map);
+        let railway_table = map.export_railway_table();
+        assert!(railway_table.contains("A,JR,JY,Loop,1067,AC 20000V 50Hz,2,\"kind=loop, line\"\n"));
+        let distance_table = map.export_distance_table();
+
+        let map = RerailMap::load(&map.save());
+        assert_integrity(&map);
+        assert_eq!(map.export_railway_table(), railway_table);
+        assert_eq!(map.export_distance_table(), distance_table);
+        let operators = map.operator_list().operators;
+        assert!(operators.len() == 1 && operators[0].id == op && operators[0].name == "JR");
+        assert!(map.is_railway_point_welded(b, 0));
+
+        // Operators added after loading do not collide with the existing ones
+        let mut result = map.add_operator("Y".to_string());
+        assert!(result.get_operator_index() != op);
+        let map = result.get_map();
+        assert_eq!(map.operator_list().operators.len(), 2);
+        assert!(map.get_railway_info(a).metadata.operator == Some(op));
+    }
+
+    #[test]
+    fn test_railways_in_viewport_closing_segment() {
+        let points = [(1000, 1000), (9000, 1000), (9000, 9000), (1000, 9000)];
+        let (map, a) = add_railway(RerailMap::new(), "ring", &points);
+        let map = map.set_railway_closed(a, true);
+        assert_integrity(&map);
+
+        // Only the segment from the last point back to the first one is in view
+        let spec = ViewportSpec {
+            left_x: 500,
+            top_y: 4000,
+            width: 100,
+            height: 100,
+            zoom: 10.0,
+        };
+        let list = map.railways_in_viewport(spec);
+        assert_eq!(list.rail_names, ["ring"]);
+        assert_eq!(list.rail_ids, [a.as_usize()]);
+    }
+
+    #[test]
+    fn test_close_empty_railway() {
+        let (map, a) = add_railway(RerailMap::new(), "a", &[(1000, 1000)]);
+        let map = map.remove_railway_point(a, 0);
+        assert!(map[a].points.is_empty());
+        let map = map.set_railway_closed(a, true);
+        assert_integrity(&map);
+        assert!(!map.is_railway_closed(a));
+    }
+
+    #[test]
+    fn test_search() {
+        let (map, a) = add_railway(
+            RerailMap::new(),
+            "Tokyo Line",
+            &[(1000, 1000), (3000, 1000)],
+        );
+        let (map, b) = add_railway(map, "Keiyo", &[(1000, 2000), (2000, 2000)]);
+        let station = |name: &str, level| StationInfo {
+            name: name.to_string(),
+            level,
+        };
+        let map = map.set_station_info(a, 0, station("Tokyo", 0));
+        let map = map.set_station_info(a, 1, station("Tokyo Teleport", 2));
+        let map = map.set_station_info(b, 0, station("Shin-Tokyo", 2));
+        let map = map.set_station_info(b, 1, station("Osaka", 2));
+        assert_integrity(&map);
+
+        // Exact matches come first, then prefixes and substrings, each by level
+        let results = map.search(" ＴＯＫＹＯ ", 10).results;
+        let names = results.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["Tokyo", "Tokyo Teleport", "Tokyo Line", "Shin-Tokyo"]
+        );
+        assert!(matches!(results[0].kind, SearchResultKind::Station));
+        assert!(matches!(results[2].kind, SearchResultKind::Railway));
+        assert_eq!(results[2].id, a.as_usize());
+        assert_eq!(results[2].level, 1);
+
+        let bounds =
+            |r: &SearchResult| (r.bounds.left, r.bounds.top, r.bounds.right, r.bounds.bottom);
+        assert_eq!(bounds(&results[0]), (1000, 1000, 1000, 1000));
+        assert_eq!(bounds(&results[2]), (1000, 1000, 3000, 1000));
+
+        assert_eq!(map.search("tokyo", 2).results.len(), 2);
+        assert!(map.search("  ", 10).results.is_empty());
+        assert!(map.search("Nagoya", 10).results.is_empty());
+    }
+
+    #[test]
+    fn test_bounds_and_fit_viewport() {
+        let map = RerailMap::new();
+        assert!(map.map_bounds().is_none());
+        assert!(map.entities_bounds(vec![], vec![]).is_none());
+        assert!(map.entities_bounds(vec![0], vec![0]).is_none());
+
+        let bounds = |b: BoundingBox| (b.left, b.top, b.right, b.bottom);
+        let (map, a) = add_railway(map, "a", &[(1000, 500)]);
+        assert_eq!(bounds(map.map_bounds().unwrap()), (1000, 500, 1000, 500));
+        assert_eq!(
+            bounds(map.railway_bounds(a).unwrap()),
+            (1000, 500, 1000, 500)
+        );
+
+        // A single point is shown at the center at the scale of 1
+        let spec = RerailMap::fit_viewport(map.map_bounds().unwrap(), 200, 100, 10);
+        assert_eq!((spec.left_x, spec.top_y, spec.zoom), (900, 450, 1.0));
+
+        let map = map.insert_railway_point(a, 1, 2000, 1000);
+        let map = add_station(map, a, 1, "s");
+        let (map, b) = add_railway(map, "b", &[(2010, 1010), (3000, 3000)]);
+        let map = map.link_to_station(b, 0, viewport(), PhysicalCoord { x: 201.0, y: 101.0 }, 5.0);
+        let s = map[a].points[1].station.unwrap();
+        assert_integrity(&map);
+        assert_eq!(
+            bounds(map.station_bounds(s).unwrap()),
+            (2000, 1000, 2000, 1000)
+        );
+        let rail_ids = vec![a.as_usize(), b.as_usize()];
+        assert_eq!(
+            bounds(
+                map.entities_bounds(vec![a.as_usize()], vec![s.as_usize()])
+                    .unwrap()
+            ),
+            (1000, 500, 2000, 1000)
+        );
+        assert_eq!(
+            bounds(map.entities_bounds(rail_ids, vec![]).unwrap()),
+            (1000, 500, 3000, 3000)
+        );
+
+        // The bounds fit in the canvas without the margin
+        let rail_bounds = map.railway_bounds(a).unwrap();
+        let spec = RerailMap::fit_viewport(rail_bounds, 300, 300, 50);
+        assert_eq!((spec.width, spec.height, spec.zoom), (300, 300, 5.0));
+        assert_eq!((spec.left_x, spec.top_y), (750, 0));
+
+        // Too large a margin leaves one pixel for the bounds
+        let spec = RerailMap::fit_viewport(rail_bounds, 300, 300, i32::MAX);
+        assert_eq!(spec.zoom, 1000.0);
+        assert_eq!((spec.left_x, spec.top_y), (1500 - 150000, 750 - 150000));
+    }
+
+    #[test]
+    fn test_snap() {
+        let points = [(1000, 1000), (2000, 1000), (3000, 1000)];
+        let (map, a) = add_railway(RerailMap::new(), "a", &points);
+        let map = add_station(map, a, 1, "s");
+        let (map, b) = add_railway(map, "b", &[(1000, 3000), (3000, 3000)]);
+        let s = map[a].points[1].station.unwrap();
+
+        let options = |editing: Option<(RailwayIndex, usize, bool)>, angle, grid| SnapOptions {
+            radius: 5.0,
+            rail_id: editing.map(|(rail_id, _, _)| rail_id.as_usize()),
+            editing_index: editing.map(|(_, index, inserting)| IndexOnRailway { index, inserting }),
+            border_point: None,
+            grid,
+            angle,
+        };
+        let snap = |x: i32, y: i32, options: SnapOptions| {
+            let result = map.snap(viewport(), x as f64 / 10.0, y as f64 / 10.0, options);
+            ((result.x, result.y), result.target)
+        };
+
+        // Stations take precedence over the points and segments at them
+        let (c, target) = snap(2020, 1010, options(None, false, None));
+        assert!(c == (2000, 1000) && matches!(target, SnapTarget::Station(t) if t == s));
+        let (c, target) = snap(1010, 1020, options(None, false, None));
+        assert!(c == (1000, 1000) && matches!(target, SnapTarget::RailwayPoint(r, 0) if r == a));
+        let (c, target) = snap(2000, 3030, options(None, false, None));
+        assert!(c == (2000, 3000) && matches!(target, SnapTarget::RailwaySegment(r, 0) if r == b));
+
+        // The point being moved and the segments of its railway are skipped
+        let (c, target) = snap(1010, 1020, options(Some((a, 0, false)), false, None));
+        assert!(c == (1010, 1020) && matches!(target, SnapTarget::None));
+        let (c, target) = snap(2020, 1010, options(Some((a, 0, false)), false, None));
+        assert!(c == (2000, 1000) && matches!(target, SnapTarget::Station(_)));
+
+        // Extending the railway from its last point
+        let appending = Some((a, 3, true));
+        let (c, target) = snap(4000, 1030, options(appending, true, Some(100)));
+        assert!(c == (4000, 1000) && matches!(target, SnapTarget::Angle));
+        let (c, target) = snap(4030, 1440, options(appending, true, Some(100)));
+        assert!(c == (4000, 1400) && matches!(target, SnapTarget::Grid));
+        let (c, target) = snap(4030, 1440, options(appending, true, None));
+        assert!(c == (4030, 1440) && matches!(target, SnapTarget::None));
     }
 }
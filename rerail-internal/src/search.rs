@@ -0,0 +1,119 @@
+const HALF_WIDTH_KATAKANA: &str = "｡｢｣､･ｦｧｨｩｪｫｬｭｮｯｰｱｲｳｴｵｶｷｸｹｺｻｼｽｾｿﾀﾁﾂﾃﾄﾅﾆﾇﾈﾉﾊﾋﾌﾍﾎﾏﾐﾑﾒﾓﾔﾕﾖﾗﾘﾙﾚﾛﾜﾝ";
+const FULL_WIDTH_KATAKANA: &str = "。「」、・ヲァィゥェォャュョッーアイウエオカキクケコサシスセソタチツテトナニヌネノハヒフヘホマミムメモヤユヨラリルレロワン";
+
+const HALF_WIDTH_VOICED_MARK: char = 'ﾞ';
+const HALF_WIDTH_SEMI_VOICED_MARK: char = 'ﾟ';
+
+fn to_full_width_katakana(c: char) -> Option<char> {
+    HALF_WIDTH_KATAKANA
+        .chars()
+        .position(|h| h == c)
+        .and_then(|i| FULL_WIDTH_KATAKANA.chars().nth(i))
+}
+
+fn with_voiced_mark(c: char) -> Option<char> {
+    match c {
+        'ウ' => Some('ヴ'),
+        'カ' | 'キ' | 'ク' | 'ケ' | 'コ' | 'サ' | 'シ' | 'ス' | 'セ' | 'ソ' | 'タ' | 'チ'
+        | 'ツ' | 'テ' | 'ト' | 'ハ' | 'ヒ' | 'フ' | 'ヘ' | 'ホ' => {
+            char::from_u32(c as u32 + 1)
+        }
+        _ => None,
+    }
+}
+
+fn with_semi_voiced_mark(c: char) -> Option<char> {
+    match c {
+        'ハ' | 'ヒ' | 'フ' | 'ヘ' | 'ホ' => char::from_u32(c as u32 + 2),
+        _ => None,
+    }
+}
+
+/// Normalizes `s` so that names can be matched regardless of the variants of characters:
+/// full-width ASCII characters are converted to half-width, half-width katakana to full-width,
+/// hiragana to katakana, and upper case letters to lower case.
+pub fn normalize(s: &str) -> String {
+    let mut ret = String::new();
+
+    for c in s.chars() {
+        let c = match c {
+            '\u{3000}' => ' ',
+            '\u{ff01}'..='\u{ff5e}' => char::from_u32(c as u32 - 0xfee0).unwrap(),
+            '\u{3041}'..='\u{3096}' | '\u{309d}' | '\u{309e}' => {
+                char::from_u32(c as u32 + 0x60).unwrap()
+            }
+            HALF_WIDTH_VOICED_MARK | HALF_WIDTH_SEMI_VOICED_MARK => {
+                let combined = ret.chars().last().and_then(|last| {
+                    if c == HALF_WIDTH_VOICED_MARK {
+                        with_voiced_mark(last)
+                    } else {
+                        with_semi_voiced_mark(last)
+                    }
+                });
+                match combined {
+                    Some(combined) => {
+                        ret.pop();
+                        combined
+                    }
+                    None if c == HALF_WIDTH_VOICED_MARK => '゛',
+                    None => '゜',
+                }
+            }
+            _ => to_full_width_katakana(c).unwrap_or(c),
+        };
+        ret.push(c.to_ascii_lowercase());
+    }
+
+    ret
+}
+
+/// How a name matches a query. Better matches compare smaller.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum MatchKind {
+    Exact,
+    Prefix,
+    Substring,
+}
+
+/// Matches a normalized name against a normalized query.
+pub fn match_name(name: &str, query: &str) -> Option<MatchKind> {
+    if name == query {
+        Some(MatchKind::Exact)
+    } else if name.starts_with(query) {
+        Some(MatchKind::Prefix)
+    } else if name.contains(query) {
+        Some(MatchKind::Substring)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("ＪＲ　Ｙａｍａｎｏｔｅ"), "jr yamanote");
+        assert_eq!(normalize("しんじゅく"), "シンジュク");
+        assert_eq!(normalize("ｼﾝｼﾞｭｸ"), "シンジュク");
+        assert_eq!(normalize("ﾊﾟﾋﾞﾘｵﾝ･ｳﾞｧ"), "パビリオン・ヴァ");
+        assert_eq!(normalize("ﾞｱﾞ"), "゛ア゛");
+        assert_eq!(normalize("東京駅"), "東京駅");
+    }
+
+    #[test]
+    fn test_match_name() {
+        assert_eq!(
+            match_name("シンジュク", "シンジュク"),
+            Some(MatchKind::Exact)
+        );
+        assert_eq!(match_name("シンジュク", "シン"), Some(MatchKind::Prefix));
+        assert_eq!(
+            match_name("ニシシンジュク", "シンジュク"),
+            Some(MatchKind::Substring)
+        );
+        assert_eq!(match_name("シブヤ", "シンジュク"), None);
+        assert!(MatchKind::Exact < MatchKind::Prefix && MatchKind::Prefix < MatchKind::Substring);
+    }
+}
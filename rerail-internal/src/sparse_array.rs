@@ -8,13 +8,40 @@ use tsify::Tsify;
 
 use std::marker::PhantomData;
 
+/// Only `data` and `unused_indices` are serialized, since maps with non-string keys are not
+/// supported by every format. `id_to_index` is rebuilt on deserialization.
 #[derive(Serialize, Deserialize)]
+#[serde(from = "SerializedSparseArray<T>")]
+#[serde(bound(deserialize = "T: Deserialize<'de>"))]
 pub struct SparseArray<T> {
     data: Vec<(SparseArrayId<T>, T)>,
+    #[serde(skip_serializing)]
     id_to_index: HashMap<SparseArrayId<T>, usize>,
     unused_indices: Vec<usize>,
 }
 
+#[derive(Deserialize)]
+struct SerializedSparseArray<T> {
+    data: Vec<(SparseArrayId<T>, T)>,
+    unused_indices: Vec<usize>,
+}
+
+impl<T> From<SerializedSparseArray<T>> for SparseArray<T> {
+    fn from(value: SerializedSparseArray<T>) -> Self {
+        let id_to_index = value
+            .data
+            .iter()
+            .enumerate()
+            .map(|(idx, (id, _))| (*id, idx))
+            .collect();
+        SparseArray {
+            data: value.data,
+            id_to_index,
+            unused_indices: value.unused_indices,
+        }
+    }
+}
+
 impl<T> SparseArray<T> {
     pub fn new() -> SparseArray<T> {
         SparseArray {
@@ -59,7 +86,6 @@ impl<T> SparseArray<T> {
         self.unused_indices.push(id.0);
     }
 
-    #[allow(unused)]
     pub fn iter(&self) -> impl Iterator<Item = &T> {
         self.data.iter().map(|(_, data)| data)
     }
@@ -107,6 +133,7 @@ impl<T> PartialEq for SparseArrayId<T> {
 
 impl<T> Eq for SparseArrayId<T> {}
 
+#[allow(clippy::non_canonical_partial_ord_impl)]
 impl<T> PartialOrd for SparseArrayId<T> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         self.0.partial_cmp(&other.0)
@@ -125,6 +152,7 @@ impl<T> Hash for SparseArrayId<T> {
     }
 }
 
+#[allow(clippy::non_canonical_clone_impl)]
 impl<T> Clone for SparseArrayId<T> {
     fn clone(&self) -> Self {
         SparseArrayId(self.0, PhantomData)
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::geom::{Coord, Rect};
+
+const CELL_SIZE: i32 = 1 << 14;
+
+// Entries spanning more cells than this are kept in a separate list and returned by every query
+const MAX_CELLS_PER_ENTRY: i64 = 64;
+
+type Cell = (i32, i32);
+
+fn cell_of(coord: Coord) -> Cell {
+    (coord.x.div_euclid(CELL_SIZE), coord.y.div_euclid(CELL_SIZE))
+}
+
+/// Uniform grid over points and line segments.
+///
+/// Queries return a superset of the entries whose bounding box overlaps the query range;
+/// callers are expected to perform exact geometric checks on the result.
+pub struct SpatialIndex<K> {
+    cells: HashMap<Cell, Vec<K>>,
+    large_entries: Vec<K>,
+    entry_cells: HashMap<K, Option<(Cell, Cell)>>,
+}
+
+impl<K> Default for SpatialIndex<K> {
+    fn default() -> Self {
+        SpatialIndex {
+            cells: HashMap::new(),
+            large_entries: vec![],
+            entry_cells: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Copy + Eq + Ord + Hash> SpatialIndex<K> {
+    #[allow(unused)]
+    pub fn new() -> SpatialIndex<K> {
+        Self::default()
+    }
+
+    #[allow(unused)]
+    pub fn len(&self) -> usize {
+        self.entry_cells.len()
+    }
+
+    pub fn insert_point(&mut self, key: K, pt: Coord) {
+        self.insert_segment(key, pt, pt);
+    }
+
+    pub fn insert_segment(&mut self, key: K, a: Coord, b: Coord) {
+        self.remove(key);
+
+        let lo = cell_of(Coord::new(a.x.min(b.x), a.y.min(b.y)));
+        let hi = cell_of(Coord::new(a.x.max(b.x), a.y.max(b.y)));
+        let num_cells = (hi.0 as i64 - lo.0 as i64 + 1) * (hi.1 as i64 - lo.1 as i64 + 1);
+
+        if num_cells > MAX_CELLS_PER_ENTRY {
+            self.large_entries.push(key);
+            self.entry_cells.insert(key, None);
+            return;
+        }
+
+        for cx in lo.0..=hi.0 {
+            for cy in lo.1..=hi.1 {
+                self.cells.entry((cx, cy)).or_default().push(key);
+            }
+        }
+        self.entry_cells.insert(key, Some((lo, hi)));
+    }
+
+    pub fn remove(&mut self, key: K) -> bool {
+        let range = match self.entry_cells.remove(&key) {
+            Some(range) => range,
+            None => return false,
+        };
+
+        match range {
+            None => {
+                let idx = self.large_entries.iter().position(|&k| k == key).unwrap();
+                self.large_entries.swap_remove(idx);
+            }
+            Some((lo, hi)) => {
+                for cx in lo.0..=hi.0 {
+                    for cy in lo.1..=hi.1 {
+                        let cell = self.cells.get_mut(&(cx, cy)).unwrap();
+                        let idx = cell.iter().position(|&k| k == key).unwrap();
+                        cell.swap_remove(idx);
+                        if cell.is_empty() {
+                            self.cells.remove(&(cx, cy));
+                        }
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Returns the entries which may intersect with `rect`, sorted and without duplicates.
+    pub fn query(&self, rect: &Rect) -> Vec<K> {
+        let lo = cell_of(rect.top_left());
+        let hi = cell_of(rect.bottom_right());
+        let num_cells = (hi.0 as i64 - lo.0 as i64 + 1) * (hi.1 as i64 - lo.1 as i64 + 1);
+
+        let mut ret = self.large_entries.clone();
+        if num_cells > self.cells.len() as i64 {
+            for (&(cx, cy), keys) in &self.cells {
+                if lo.0 <= cx && cx <= hi.0 && lo.1 <= cy && cy <= hi.1 {
+                    ret.extend(keys);
+                }
+            }
+        } else {
+            for cx in lo.0..=hi.0 {
+                for cy in lo.1..=hi.1 {
+                    if let Some(keys) = self.cells.get(&(cx, cy)) {
+                        ret.extend(keys);
+                    }
+                }
+            }
+        }
+
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    /// Returns the entries which may lie within `radius` of `center`.
+    pub fn query_around(&self, center: Coord, radius: i32) -> Vec<K> {
+        let radius = radius.max(0);
+        self.query(&Rect::new(
+            center.y.saturating_sub(radius),
+            center.y.saturating_add(radius),
+            center.x.saturating_sub(radius),
+            center.x.saturating_add(radius),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random_coords(n: usize, range: i32) -> Vec<Coord> {
+        let mut state = 12345u64;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((state >> 33) % range as u64) as i32
+        };
+        (0..n).map(|_| Coord::new(next(), next())).collect()
+    }
+
+    fn brute_force(points: &[Coord], rect: &Rect) -> Vec<usize> {
+        (1..points.len())
+            .filter(|&i| rect.crosses_with_line_segment(points[i - 1], points[i]))
+            .collect()
+    }
+
+    fn indexed(index: &SpatialIndex<usize>, points: &[Coord], rect: &Rect) -> Vec<usize> {
+        index
+            .query(rect)
+            .into_iter()
+            .filter(|&i| i > 0 && rect.crosses_with_line_segment(points[i - 1], points[i]))
+            .collect()
+    }
+
+    fn build_polyline(n: usize) -> Vec<Coord> {
+        let mut points = vec![Coord::new(0, 0)];
+        for d in pseudo_random_coords(n - 1, 20000) {
+            let last = *points.last().unwrap();
+            points.push(Coord::new(last.x + d.x - 10000, last.y + d.y - 10000));
+        }
+        points
+    }
+
+    #[test]
+    fn test_spatial_index_query() {
+        let points = build_polyline(2000);
+        let mut index = SpatialIndex::new();
+        for i in 1..points.len() {
+            index.insert_segment(i, points[i - 1], points[i]);
+        }
+        index.insert_segment(
+            0,
+            Coord::new(-1000000, -1000000),
+            Coord::new(1000000, 1000000),
+        );
+        assert_eq!(index.len(), points.len());
+
+        for c in pseudo_random_coords(50, 400000) {
+            let rect = Rect::new(c.y - 200000, c.y - 150000, c.x - 200000, c.x - 170000);
+            assert_eq!(indexed(&index, &points, &rect), brute_force(&points, &rect));
+            assert!(index.query(&rect).contains(&0));
+        }
+
+        for i in (1..points.len()).step_by(2) {
+            assert!(index.remove(i));
+        }
+        assert!(!index.remove(1));
+        let rect = Rect::new(-500000, 500000, -500000, 500000);
+        assert!(index.query(&rect).iter().all(|&i| i % 2 == 0));
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_spatial_index_query() {
+        let points = build_polyline(200000);
+        let mut index = SpatialIndex::new();
+        for i in 1..points.len() {
+            index.insert_segment(i, points[i - 1], points[i]);
+        }
+        let rects = pseudo_random_coords(200, 2000000)
+            .into_iter()
+            .map(|c| {
+                let (x, y) = (c.x - 1000000, c.y - 1000000);
+                Rect::new(y, y + 100000, x, x + 120000)
+            })
+            .collect::<Vec<_>>();
+
+        let start = std::time::Instant::now();
+        let brute = rects
+            .iter()
+            .map(|r| brute_force(&points, r).len())
+            .sum::<usize>();
+        let brute_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let fast = rects
+            .iter()
+            .map(|r| indexed(&index, &points, r).len())
+            .sum::<usize>();
+        let indexed_time = start.elapsed();
+
+        assert_eq!(brute, fast);
+        println!(
+            "linear scan: {:?}, spatial index: {:?}",
+            brute_time, indexed_time
+        );
+    }
+}
@@ -10,7 +10,7 @@ import iconRailway from "./assets/railway.svg";
 import iconStation from "./assets/station.svg";
 import iconBorders from "./assets/borders.svg";
 import { ButtonGroup, IconButton } from "@mui/material";
-import { FileOpen, Save } from "@mui/icons-material";
+import { FileOpen, Save, TableChart } from "@mui/icons-material";
 import { BorderSelecter } from "./BorderSelecter";
 
 type RerailAppState = {
@@ -64,6 +64,20 @@ function App() {
     a.click();
     URL.revokeObjectURL(url);
   };
+  const downloadDistanceTable = () => {
+    const map = appState.railwayMap;
+    if (map === null) {
+      return;
+    }
+    const data = map.exportDistanceTable();
+    const blob = new Blob([data], { type: "text/csv" });
+    const url = URL.createObjectURL(blob);
+    const a = anchorElementRef.current!;
+    a.download = "distances.csv";
+    a.href = url;
+    a.click();
+    URL.revokeObjectURL(url);
+  };
   const onKeyDown = (e: KeyboardEvent) => {
     // do not respond if the focus is on an input element
     if (document.activeElement instanceof HTMLInputElement) {
@@ -142,6 +156,13 @@ function App() {
           >
             <Save />
           </IconButton>
+          <IconButton
+            size="small"
+            onClick={downloadDistanceTable}
+            disabled={appState.railwayMap === null}
+          >
+            <TableChart />
+          </IconButton>
         </ButtonGroup>
         <div
           style={{
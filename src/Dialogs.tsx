@@ -5,12 +5,23 @@ import {
   DialogContent,
   DialogTitle,
   FormControlLabel,
+  List,
+  ListItemButton,
+  ListItemText,
+  MenuItem,
   Radio,
   RadioGroup,
   TextField,
 } from "@mui/material";
 import { ChangeEvent, forwardRef, useImperativeHandle, useState } from "react";
-import { RailwayInfo, StationInfo, StationListOnRailway } from "./RerailMap";
+import {
+  Electrification,
+  OperatorInfo,
+  RailwayInfo,
+  StationCandidate,
+  StationInfo,
+  StationListOnRailway,
+} from "./RerailMap";
 import { MuiColorInput } from "mui-color-input";
 
 type StationDialogState = {
@@ -135,44 +146,160 @@ export const StationDialog = forwardRef((_props, ref) => {
 type RailwayDialogState = {
   open: boolean;
   value: RailwayInfo;
-  callback?: (value?: RailwayInfo) => void;
+  // operators as edited in the dialog
+  operators: OperatorInfo[];
+  removedOperators: number[];
+  // name of the operator to be added, or null to choose an existing one
+  newOperator: string | null;
+  // numbers and lists are kept as typed, and parsed on OK
+  gauge: string;
+  electrification: "" | "none" | "dc" | "ac";
+  voltage: string;
+  frequency: string;
+  numTracks: string;
+  alternativeNames: string;
+  attributes: string;
+  callback?: (value?: RailwayDialogResult) => void;
+};
+
+export type RailwayDialogResult = {
+  info: RailwayInfo;
+  // operators after renaming, and those removed in the dialog
+  operators: OperatorInfo[];
+  removedOperators: number[];
+  // operator to be added and assigned to the railway
+  newOperator?: string;
 };
 
 export type RailwayDialogRefType = {
-  open: (initialValue: RailwayInfo) => Promise<RailwayInfo | undefined>;
+  open: (
+    initialValue: RailwayInfo,
+    operators: OperatorInfo[],
+  ) => Promise<RailwayDialogResult | undefined>;
+};
+
+// empty or invalid input is an unknown value
+const parseNonNegativeInteger = (s: string): number | undefined => {
+  const n = Number(s.trim());
+  return s.trim() !== "" && Number.isInteger(n) && n >= 0 ? n : undefined;
+};
+
+const initialRailwayDialogState = (
+  value: RailwayInfo,
+  operators: OperatorInfo[],
+): RailwayDialogState => {
+  const metadata = value.metadata ?? {};
+  const electrification = metadata.electrification;
+  let kind: RailwayDialogState["electrification"] = "";
+  let voltage = "";
+  let frequency = "";
+  if (electrification === "none") {
+    kind = "none";
+  } else if (electrification !== undefined && "dc" in electrification) {
+    kind = "dc";
+    voltage = String(electrification.dc.voltage);
+  } else if (electrification !== undefined && "ac" in electrification) {
+    kind = "ac";
+    voltage = String(electrification.ac.voltage);
+    frequency = String(electrification.ac.frequency);
+  }
+  return {
+    open: true,
+    value,
+    operators,
+    removedOperators: [],
+    newOperator: null,
+    gauge: metadata.gauge?.toString() ?? "",
+    electrification: kind,
+    voltage,
+    frequency,
+    numTracks: metadata.numTracks?.toString() ?? "",
+    alternativeNames: (metadata.alternativeNames ?? []).join(";"),
+    attributes: (metadata.attributes ?? [])
+      .map(([key, value]) => `${key}=${value}`)
+      .join("\n"),
+  };
 };
 
 export const RailwayDialog = forwardRef((_props, ref) => {
   const [state, setState] = useState<RailwayDialogState>({
+    ...initialRailwayDialogState({ name: "", color: 0x000000, level: 0 }, []),
     open: false,
-    value: {
-      name: "",
-      color: 0x000000,
-      level: 0,
-    },
   });
 
   useImperativeHandle(
     ref,
     () => {
       return {
-        open(initialValue: RailwayInfo): Promise<RailwayInfo | undefined> {
-          return new Promise((resolve: (value?: RailwayInfo) => void) => {
-            setState({
-              open: true,
-              value: initialValue,
-              callback: resolve,
-            });
-          });
+        open(
+          initialValue: RailwayInfo,
+          operators: OperatorInfo[],
+        ): Promise<RailwayDialogResult | undefined> {
+          return new Promise(
+            (resolve: (value?: RailwayDialogResult) => void) => {
+              setState({
+                ...initialRailwayDialogState(initialValue, operators),
+                callback: resolve,
+              });
+            },
+          );
         },
       };
     },
     [],
   );
 
+  const result = (): RailwayDialogResult => {
+    const voltage = parseNonNegativeInteger(state.voltage);
+    const frequency = parseNonNegativeInteger(state.frequency);
+    let electrification: Electrification | undefined = undefined;
+    if (state.electrification === "none") {
+      electrification = "none";
+    } else if (state.electrification === "dc" && voltage !== undefined) {
+      electrification = { dc: { voltage } };
+    } else if (
+      state.electrification === "ac" &&
+      voltage !== undefined &&
+      frequency !== undefined
+    ) {
+      electrification = { ac: { voltage, frequency } };
+    }
+    const attributes = state.attributes
+      .split("\n")
+      .filter((line) => line.includes("="))
+      .map((line): [string, string] => {
+        const k = line.indexOf("=");
+        return [line.slice(0, k).trim(), line.slice(k + 1).trim()];
+      });
+    const newOperator = state.newOperator?.trim();
+    return {
+      info: {
+        ...state.value,
+        metadata: {
+          ...state.value.metadata,
+          operator:
+            state.newOperator === null
+              ? state.value.metadata?.operator
+              : undefined,
+          gauge: parseNonNegativeInteger(state.gauge),
+          electrification,
+          numTracks: parseNonNegativeInteger(state.numTracks),
+          alternativeNames: state.alternativeNames
+            .split(";")
+            .map((name) => name.trim())
+            .filter((name) => name !== ""),
+          attributes,
+        },
+      },
+      operators: state.operators,
+      removedOperators: state.removedOperators,
+      newOperator: newOperator === "" ? undefined : newOperator,
+    };
+  };
+
   const onClick = (ok: boolean) => {
-    state.callback!(ok ? state.value : undefined);
-    setState({ open: false, value: state.value, callback: undefined });
+    state.callback!(ok ? result() : undefined);
+    setState({ ...state, open: false, callback: undefined });
   };
 
   const onChangeName = (e: ChangeEvent<HTMLInputElement>) => {
@@ -195,6 +322,71 @@ export const RailwayDialog = forwardRef((_props, ref) => {
     });
   };
 
+  const onChangeLineCode = (e: ChangeEvent<HTMLInputElement>) => {
+    const lineCode = e.target.value;
+    setState({
+      ...state,
+      value: {
+        ...state.value,
+        metadata: {
+          ...state.value.metadata,
+          lineCode: lineCode === "" ? undefined : lineCode,
+        },
+      },
+    });
+  };
+
+  // "" for unknown, "new" for a new operator, or the id of an operator
+  const onChangeOperator = (e: ChangeEvent<HTMLInputElement>) => {
+    const choice = e.target.value;
+    setState({
+      ...state,
+      value: {
+        ...state.value,
+        metadata: {
+          ...state.value.metadata,
+          operator:
+            choice === "" || choice === "new" ? undefined : Number(choice),
+        },
+      },
+      newOperator: choice === "new" ? "" : null,
+    });
+  };
+
+  const onChangeOperatorName = (e: ChangeEvent<HTMLInputElement>) => {
+    const name = e.target.value;
+    if (state.newOperator !== null) {
+      setState({ ...state, newOperator: name });
+      return;
+    }
+    const id = state.value.metadata?.operator;
+    setState({
+      ...state,
+      operators: state.operators.map((op) =>
+        op.id === id ? { ...op, name } : op,
+      ),
+    });
+  };
+
+  const onRemoveOperator = () => {
+    const id = state.value.metadata?.operator;
+    if (id === undefined) {
+      return;
+    }
+    setState({
+      ...state,
+      value: {
+        ...state.value,
+        metadata: {
+          ...state.value.metadata,
+          operator: undefined,
+        },
+      },
+      operators: state.operators.filter((op) => op.id !== id),
+      removedOperators: [...state.removedOperators, id],
+    });
+  };
+
   const onChangeColor = (color: string) => {
     const r = parseInt(color.slice(1, 3), 16);
     const g = parseInt(color.slice(3, 5), 16);
@@ -219,6 +411,12 @@ export const RailwayDialog = forwardRef((_props, ref) => {
   };
 
   const color = "#" + ("000000" + state.value.color.toString(16)).slice(-6);
+  const operatorId = state.value.metadata?.operator;
+  const operatorChoice =
+    state.newOperator !== null ? "new" : operatorId?.toString() ?? "";
+  const operatorName =
+    state.newOperator ??
+    state.operators.find((op) => op.id === operatorId)?.name;
 
   return (
     <Dialog open={state.open} onKeyDown={onKeyDown}>
@@ -232,6 +430,124 @@ export const RailwayDialog = forwardRef((_props, ref) => {
             onChange={onChangeName}
           />
         </div>
+        <div>
+          <TextField
+            label="路線記号"
+            margin="dense"
+            value={state.value.metadata?.lineCode ?? ""}
+            onChange={onChangeLineCode}
+          />
+        </div>
+        <div>
+          <TextField
+            label="別名 (;区切り)"
+            margin="dense"
+            value={state.alternativeNames}
+            onChange={(e) =>
+              setState({ ...state, alternativeNames: e.target.value })
+            }
+          />
+        </div>
+        <div>
+          <TextField
+            select
+            label="事業者"
+            margin="dense"
+            style={{ minWidth: 200 }}
+            value={operatorChoice}
+            onChange={onChangeOperator}
+          >
+            <MenuItem value="">不明</MenuItem>
+            {state.operators.map((op) => (
+              <MenuItem key={op.id} value={op.id.toString()}>
+                {op.name}
+              </MenuItem>
+            ))}
+            <MenuItem value="new">新しい事業者</MenuItem>
+          </TextField>
+          {operatorName !== undefined && (
+            <TextField
+              label="事業者名"
+              margin="dense"
+              value={operatorName}
+              onChange={onChangeOperatorName}
+            />
+          )}
+          {state.newOperator === null && operatorId !== undefined && (
+            <Button onClick={onRemoveOperator}>事業者を削除</Button>
+          )}
+        </div>
+        <div>
+          <TextField
+            label="軌間 (mm)"
+            margin="dense"
+            value={state.gauge}
+            onChange={(e) => setState({ ...state, gauge: e.target.value })}
+          />
+          <TextField
+            label="線路数"
+            margin="dense"
+            value={state.numTracks}
+            onChange={(e) => setState({ ...state, numTracks: e.target.value })}
+          />
+        </div>
+        <div>
+          <TextField
+            select
+            label="電化"
+            margin="dense"
+            style={{ minWidth: 120 }}
+            value={state.electrification}
+            onChange={(e) =>
+              setState({
+                ...state,
+                electrification: e.target
+                  .value as RailwayDialogState["electrification"],
+              })
+            }
+          >
+            <MenuItem value="">不明</MenuItem>
+            <MenuItem value="none">非電化</MenuItem>
+            <MenuItem value="dc">直流</MenuItem>
+            <MenuItem value="ac">交流</MenuItem>
+          </TextField>
+          {(state.electrification === "dc" ||
+            state.electrification === "ac") && (
+            <TextField
+              label="電圧 (V)"
+              margin="dense"
+              value={state.voltage}
+              onChange={(e) => setState({ ...state, voltage: e.target.value })}
+            />
+          )}
+          {state.electrification === "ac" && (
+            <TextField
+              label="周波数 (Hz)"
+              margin="dense"
+              value={state.frequency}
+              onChange={(e) =>
+                setState({ ...state, frequency: e.target.value })
+              }
+            />
+          )}
+        </div>
+        <div>
+          <TextField
+            label="属性 (1行に1つ、キー=値)"
+            margin="dense"
+            multiline
+            fullWidth
+            minRows={2}
+            value={state.attributes}
+            onChange={(e) => setState({ ...state, attributes: e.target.value })}
+            onKeyDown={(e) => {
+              // new lines are part of the input rather than OK
+              if (e.key === "Enter") {
+                e.stopPropagation();
+              }
+            }}
+          />
+        </div>
         <div>
           <MuiColorInput
             format="hex"
@@ -278,93 +594,161 @@ export const RailwayDialog = forwardRef((_props, ref) => {
 });
 
 export type StationListDialogRefType = {
-  open: (initialValue: StationListOnRailway) => void;
+  open: (railId: number, initialValue: StationListOnRailway) => void;
 };
 
-export const StationListDialog = forwardRef((_props, ref) => {
-  const [isOpen, setIsOpen] = useState(false);
-  const [stationList, setStationList] = useState<StationListOnRailway | null>(
-    null,
-  );
-
-  useImperativeHandle(
-    ref,
-    () => {
-      return {
-        open(s: StationListOnRailway) {
-          setStationList(s);
-          setIsOpen(true);
-        },
-      };
-    },
-    [],
-  );
+type StationListDialogProps = {
+  // sets (or clears if undefined) the official distance of the station at the
+  // point of the railway, and returns the updated station list
+  onSetOfficialDistance: (
+    railId: number,
+    index: number,
+    distance?: number,
+  ) => StationListOnRailway | undefined;
+};
 
-  let rows = [];
-  if (stationList !== null) {
-    const names = stationList.names;
-    const distances = stationList.distances;
-    for (let i = 0; i < names.length; ++i) {
-      rows.push(
-        <tr>
-          <td style={{ width: "70%", zIndex: 2 }}>{names[i]}</td>
-          <td style={{ width: "30%", zIndex: 2 }}>
-            {(distances[i] / 1000).toFixed(2)}
-          </td>
-        </tr>,
+export const StationListDialog = forwardRef(
+  (props: StationListDialogProps, ref) => {
+    const [isOpen, setIsOpen] = useState(false);
+    const [railId, setRailId] = useState<number | null>(null);
+    const [stationList, setStationList] = useState<StationListOnRailway | null>(
+      null,
+    );
+
+    useImperativeHandle(
+      ref,
+      () => {
+        return {
+          open(id: number, s: StationListOnRailway) {
+            setRailId(id);
+            setStationList(s);
+            setIsOpen(true);
+          },
+        };
+      },
+      [],
+    );
+
+    // empty input clears the official distance
+    const onCommitOfficialDistance = (index: number, value: string) => {
+      if (railId === null) {
+        return;
+      }
+      const distance = value.trim() === "" ? undefined : Number(value);
+      if (distance !== undefined && !Number.isFinite(distance)) {
+        return;
+      }
+      const newStationList = props.onSetOfficialDistance(
+        railId,
+        index,
+        distance,
       );
+      if (newStationList !== undefined) {
+        setStationList(newStationList);
+      }
+    };
+
+    let rows = [];
+    if (stationList !== null) {
+      const names = stationList.names;
+      const distances = stationList.distances;
+      const branchStarts = stationList.branch_starts;
+      const points = stationList.points;
+      const official = stationList.official;
+      for (let i = 0; i < names.length; ++i) {
+        if (i > 0 && branchStarts.includes(i)) {
+          rows.push(
+            <tr>
+              <td colSpan={2} style={{ backgroundColor: "#eeeeee" }}></td>
+            </tr>,
+          );
+        }
+        const isOfficial = official.includes(i);
+        // the first station of a ring appears again at the end (not editable)
+        const isRepeated = points.indexOf(points[i]) < i;
+        rows.push(
+          <tr>
+            <td style={{ width: "70%", zIndex: 2 }}>{names[i]}</td>
+            <td style={{ width: "30%", zIndex: 2 }}>
+              {isRepeated ? (
+                distances[i].toFixed(2)
+              ) : (
+                <TextField
+                  key={`${i}:${points[i]}:${isOfficial ? distances[i] : ""}`}
+                  variant="standard"
+                  size="small"
+                  defaultValue={isOfficial ? String(distances[i]) : ""}
+                  placeholder={distances[i].toFixed(2)}
+                  inputProps={{
+                    style: { fontWeight: isOfficial ? "bold" : "normal" },
+                  }}
+                  onBlur={(e) =>
+                    onCommitOfficialDistance(points[i], e.target.value)
+                  }
+                  onKeyDown={(e) => {
+                    if (e.key === "Enter") {
+                      e.preventDefault();
+                      (e.target as HTMLInputElement).blur();
+                    }
+                  }}
+                />
+              )}
+            </td>
+          </tr>,
+        );
+      }
     }
-  }
-
-  const onKeyDown = (e: React.KeyboardEvent) => {
-    if (e.key === "Escape") {
-      e.preventDefault();
-      setIsOpen(false);
-    }
-  };
 
-  return (
-    <Dialog open={isOpen} onKeyDown={onKeyDown} fullWidth>
-      <DialogTitle>駅一覧</DialogTitle>
-      <DialogContent style={{ height: "100%" }}>
-        <table style={{ width: "100%" }} cellSpacing={0} border={1}>
-          <thead style={{ position: "sticky" }}>
-            <tr>
-              <th
-                style={{
-                  width: "70%",
-                  position: "sticky",
-                  top: 0,
-                  zIndex: 1,
-                  whiteSpace: "nowrap",
-                  backgroundColor: "#eeeeee",
-                }}
-              >
-                駅名
-              </th>
-              <th
-                style={{
-                  width: "30%",
-                  position: "sticky",
-                  top: 0,
-                  zIndex: 1,
-                  whiteSpace: "nowrap",
-                  backgroundColor: "#eeeeee",
-                }}
-              >
-                距離(km)
-              </th>
-            </tr>
-          </thead>
-          {rows}
-        </table>
-      </DialogContent>
-      <DialogActions>
-        <Button onClick={() => setIsOpen(false)}>閉じる</Button>
-      </DialogActions>
-    </Dialog>
-  );
-});
+    const onKeyDown = (e: React.KeyboardEvent) => {
+      if (e.key === "Escape") {
+        e.preventDefault();
+        setIsOpen(false);
+      }
+    };
+
+    return (
+      <Dialog open={isOpen} onKeyDown={onKeyDown} fullWidth>
+        <DialogTitle>駅一覧</DialogTitle>
+        <DialogContent style={{ height: "100%" }}>
+          <table style={{ width: "100%" }} cellSpacing={0} border={1}>
+            <thead style={{ position: "sticky" }}>
+              <tr>
+                <th
+                  style={{
+                    width: "70%",
+                    position: "sticky",
+                    top: 0,
+                    zIndex: 1,
+                    whiteSpace: "nowrap",
+                    backgroundColor: "#eeeeee",
+                  }}
+                >
+                  駅名
+                </th>
+                <th
+                  style={{
+                    width: "30%",
+                    position: "sticky",
+                    top: 0,
+                    zIndex: 1,
+                    whiteSpace: "nowrap",
+                    backgroundColor: "#eeeeee",
+                  }}
+                >
+                  距離(km)
+                </th>
+              </tr>
+            </thead>
+            {rows}
+          </table>
+        </DialogContent>
+        <DialogActions>
+          <Button onClick={() => setIsOpen(false)}>閉じる</Button>
+        </DialogActions>
+      </Dialog>
+    );
+  },
+);
 
 // confirmation dialog with the same interface as the above
 // input: prompt text
@@ -426,3 +810,77 @@ export const ConfirmationDialog = forwardRef((_props, ref) => {
     </Dialog>
   );
 });
+
+// dialog to choose one of the stations to link a railway point to
+// output: the chosen candidate, or undefined if cancelled (as a Promise)
+export type StationChoiceDialogRefType = {
+  open: (
+    candidates: StationCandidate[],
+  ) => Promise<StationCandidate | undefined>;
+};
+
+export const StationChoiceDialog = forwardRef((_props, ref) => {
+  const [state, setState] = useState<{
+    open: boolean;
+    candidates: StationCandidate[];
+    callback: (value?: StationCandidate) => void;
+  }>({
+    open: false,
+    candidates: [],
+    callback: (_value?: StationCandidate) => {},
+  });
+
+  useImperativeHandle(
+    ref,
+    () => {
+      return {
+        open(c: StationCandidate[]): Promise<StationCandidate | undefined> {
+          return new Promise(
+            (resolve: (value?: StationCandidate) => void) => {
+              setState({
+                open: true,
+                candidates: c,
+                callback: resolve,
+              });
+            },
+          );
+        },
+      };
+    },
+    [],
+  );
+
+  const onClose = (value?: StationCandidate) => {
+    state.callback(value);
+    setState({
+      open: false,
+      candidates: [],
+      callback: (_value?: StationCandidate) => {},
+    });
+  };
+
+  const onKeyDown = (e: React.KeyboardEvent) => {
+    if (e.key === "Escape") {
+      e.preventDefault();
+      onClose();
+    }
+  };
+
+  return (
+    <Dialog open={state.open} onKeyDown={onKeyDown}>
+      <DialogTitle>接続する駅</DialogTitle>
+      <DialogContent>
+        <List>
+          {state.candidates.map((c) => (
+            <ListItemButton key={c.station} onClick={() => onClose(c)}>
+              <ListItemText primary={c.name} />
+            </ListItemButton>
+          ))}
+        </List>
+      </DialogContent>
+      <DialogActions>
+        <Button onClick={() => onClose()}>キャンセル</Button>
+      </DialogActions>
+    </Dialog>
+  );
+});
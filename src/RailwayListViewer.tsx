@@ -9,6 +9,10 @@ type RailwayListViewerProps = {
   onSelect: (id: number) => void;
   onOpenRailwayConfig: (id: number) => void;
   onOpenStationList: (id: number) => void;
+  onToggleRailwayClosed: (id: number) => void;
+  onToggleRailwaySmooth: (id: number) => void;
+  onReverseRailway: (id: number) => void;
+  hasOfficialDistances: (id: number) => boolean;
   onDeleteRailway: (id: number) => void;
 };
 
@@ -96,6 +100,45 @@ export const RailwayListViewer = (props: RailwayListViewerProps) => {
         >
           駅一覧
         </MenuItem>
+        <MenuItem
+          onClick={() => {
+            if (contextMenu !== null) {
+              props.onToggleRailwayClosed(contextMenu.selectedRail);
+            }
+            setContextMenu(null);
+          }}
+        >
+          環状線の切り替え
+        </MenuItem>
+        <MenuItem
+          onClick={() => {
+            if (contextMenu !== null) {
+              props.onToggleRailwaySmooth(contextMenu.selectedRail);
+            }
+            setContextMenu(null);
+          }}
+        >
+          曲線表示の切り替え
+        </MenuItem>
+        <MenuItem
+          onClick={async () => {
+            if (contextMenu !== null) {
+              setContextMenu(null);
+              const id = contextMenu.selectedRail;
+              // official distances are measured from the start and get lost
+              const result =
+                !props.hasOfficialDistances(id) ||
+                (await confirmationDialogRef.current!.open(
+                  "公式距離が消去されます。路線の向きを反転しますか？",
+                ));
+              if (result) {
+                props.onReverseRailway(id);
+              }
+            }
+          }}
+        >
+          路線の向きを反転
+        </MenuItem>
         <MenuItem
           onClick={async () => {
             if (contextMenu !== null) {
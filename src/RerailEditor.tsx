@@ -1,5 +1,6 @@
 import { useState, useRef, useEffect } from "react";
 import {
+  RailwayInfo,
   RerailMap,
   ViewportSpec,
   ViewportRailwayList,
@@ -11,6 +12,9 @@ import { RailwayListViewer } from "./RailwayListViewer";
 import {
   RailwayDialog,
   RailwayDialogRefType,
+  RailwayDialogResult,
+  StationChoiceDialog,
+  StationChoiceDialogRefType,
   StationDialog,
   StationDialogRefType,
   StationListDialog,
@@ -90,11 +94,51 @@ export const RerailEditor = (props: RerailEditorProps) => {
   const stationDialogRef = useRef<StationDialogRefType>(null);
   const railwayDialogRef = useRef<RailwayDialogRefType>(null);
   const stationListDialogRef = useRef<StationListDialogRefType>(null);
+  const stationChoiceDialogRef = useRef<StationChoiceDialogRefType>(null);
 
   const [state, setState] = useState<RerailEditorStateType>(
     initialRerailEditorState,
   );
 
+  // find the railway at (x, y), preferring stations and points to segments
+  const pickRailway = (x: number, y: number): number | null => {
+    const hits = props.railwayMap!.pick(viewport, x, y, distanceThreshold).hits;
+    for (const hit of hits) {
+      const entity = hit.entity;
+      if ("station" in entity) {
+        return entity.station[1];
+      } else if ("railwayPoint" in entity) {
+        return entity.railwayPoint[0];
+      } else if ("railwaySegment" in entity) {
+        return entity.railwaySegment[0];
+      }
+    }
+    return null;
+  };
+
+  // find the railway point at (x, y) other than the index-th point of the railway
+  const pickWeldTarget = (
+    x: number,
+    y: number,
+    railId: number,
+    index: number,
+  ): [number, number] | null => {
+    const hits = props.railwayMap!.pick(viewport, x, y, distanceThreshold).hits;
+    for (const hit of hits) {
+      const entity = hit.entity;
+      let point: [number, number] | null = null;
+      if ("station" in entity) {
+        point = [entity.station[1], entity.station[2]];
+      } else if ("railwayPoint" in entity) {
+        point = entity.railwayPoint;
+      }
+      if (point !== null && (point[0] !== railId || point[1] !== index)) {
+        return point;
+      }
+    }
+    return null;
+  };
+
   const viewport = {
     leftX: props.topX,
     topY: props.topY,
@@ -238,17 +282,24 @@ export const RerailEditor = (props: RerailEditorProps) => {
       });
     } else if (editorMode === "newRailway") {
       // get railway info using dialog, then add a new railway with point (x, y) and the info
-      const railwayInfo = await railwayDialogRef.current!.open({
-        name: "",
-        color: 0,
-        level: 0,
-      });
-      if (railwayInfo === undefined) {
+      const result = await railwayDialogRef.current!.open(
+        {
+          name: "",
+          color: 0,
+          level: 0,
+        },
+        props.railwayMap!.operatorList().operators,
+      );
+      if (result === undefined) {
         return;
       }
+      const [railwayMap, railwayInfo] = applyRailwayDialogResult(
+        props.railwayMap!,
+        result,
+      );
       const logicalX = x * zoomLevels[props.zoomLevel] + props.topX;
       const logicalY = y * zoomLevels[props.zoomLevel] + props.topY;
-      const newMapAndIndex = props.railwayMap!.newRailwayFromInfo(
+      const newMapAndIndex = railwayMap.newRailwayFromInfo(
         railwayInfo,
         logicalX,
         logicalY,
@@ -264,6 +315,10 @@ export const RerailEditor = (props: RerailEditorProps) => {
       });
     } else if (editorMode === "railway") {
       if (state.selectedRailId === null) {
+        const railId = pickRailway(x, y);
+        if (railId !== null) {
+          setState({ ...state, selectedRailId: railId });
+        }
         return;
       }
       const viewport: ViewportSpec = {
@@ -300,15 +355,35 @@ export const RerailEditor = (props: RerailEditorProps) => {
               selectedIndex: { index: numPoints, inserting: true },
               mouse: { x, y },
             });
+          } else {
+            // start a new branch from the point
+            props.setRailwayMap(
+              props.railwayMap!.addRailwayBranch(
+                state.selectedRailId!,
+                nearest.index,
+              ),
+            );
+            setState({
+              ...state,
+              editorPhase: "point-moving",
+              selectedIndex: { index: numPoints + 1, inserting: true },
+              mouse: { x, y },
+            });
           }
         }
       } else if (e.button === 2) {
         if (nearest && !nearest.inserting) {
+          // with alt-key, only detach the point from the points welded to it
           props.setRailwayMap(
-            props.railwayMap!.removeRailwayPoint(
-              state.selectedRailId!,
-              nearest.index,
-            ),
+            e.altKey
+              ? props.railwayMap!.unweldRailwayPoint(
+                  state.selectedRailId!,
+                  nearest.index,
+                )
+              : props.railwayMap!.removeRailwayPoint(
+                  state.selectedRailId!,
+                  nearest.index,
+                ),
           );
         }
       } else {
@@ -323,6 +398,10 @@ export const RerailEditor = (props: RerailEditorProps) => {
       }
     } else if (editorMode === "station") {
       if (state.selectedRailId === null) {
+        const railId = pickRailway(x, y);
+        if (railId !== null) {
+          setState({ ...state, selectedRailId: railId });
+        }
         return;
       }
       const nearest = props.railwayMap?.findNearestSegment(
@@ -428,7 +507,9 @@ export const RerailEditor = (props: RerailEditorProps) => {
     }
   };
 
-  const canvasMouseUpHandler = async () => {
+  const canvasMouseUpHandler = async (
+    e: React.MouseEvent<HTMLCanvasElement, MouseEvent>,
+  ) => {
     if (state.editorPhase === "viewport-moving") {
       setState({
         ...state,
@@ -439,11 +520,33 @@ export const RerailEditor = (props: RerailEditorProps) => {
         topYOnMouseDown: undefined,
       });
     } else if (state.editorPhase === "point-moving") {
-      const x = state.mouse!.x * zoomLevels[props.zoomLevel] + props.topX;
-      const y = state.mouse!.y * zoomLevels[props.zoomLevel] + props.topY;
-
       const map = props.railwayMap!;
-      if (state.selectedIndex!.inserting) {
+      const { x, y } = map.snap(viewport, state.mouse!.x, state.mouse!.y, {
+        radius: distanceThreshold,
+        railId: state.selectedRailId!,
+        editingIndex: state.selectedIndex!,
+      });
+      // with alt-key, weld the point to the point it is dropped on
+      const weldTarget =
+        e.altKey && !state.selectedIndex!.inserting
+          ? pickWeldTarget(
+              state.mouse!.x,
+              state.mouse!.y,
+              state.selectedRailId!,
+              state.selectedIndex!.index,
+            )
+          : null;
+
+      if (weldTarget !== null) {
+        props.setRailwayMap(
+          map.weldRailwayPoints(
+            weldTarget[0],
+            weldTarget[1],
+            state.selectedRailId!,
+            state.selectedIndex!.index,
+          ),
+        );
+      } else if (state.selectedIndex!.inserting) {
         props.setRailwayMap(
           map.insertRailwayPoint(
             state.selectedRailId!,
@@ -469,15 +572,28 @@ export const RerailEditor = (props: RerailEditorProps) => {
       });
     } else if (state.editorPhase === "station-linking") {
       if (state.moved) {
-        // link station
-        props.setRailwayMap(
-          props.railwayMap!.linkToStation(
-            state.selectedRailId!,
-            state.selectedIndex!.index,
-            viewport,
-            state.mouse!,
-          ),
-        );
+        // link station, asking which one if more than one station is near
+        const map = props.railwayMap!;
+        const candidates = map.stationCandidatesForLink(
+          state.selectedRailId!,
+          state.selectedIndex!.index,
+          viewport,
+          state.mouse!,
+          distanceThreshold,
+        ).candidates;
+        const candidate =
+          candidates.length > 1
+            ? await stationChoiceDialogRef.current!.open(candidates)
+            : candidates[0];
+        if (candidate !== undefined) {
+          props.setRailwayMap(
+            map.linkToStationCandidate(
+              state.selectedRailId!,
+              state.selectedIndex!.index,
+              candidate,
+            ),
+          );
+        }
         setState({
           ...state,
           editorPhase: "none",
@@ -535,11 +651,13 @@ export const RerailEditor = (props: RerailEditorProps) => {
       });
     } else if (state.editorPhase === "border-adding") {
       const map = props.railwayMap!;
-      const x = state.mouse!.x * zoomLevels[props.zoomLevel] + props.topX;
-      const y = state.mouse!.y * zoomLevels[props.zoomLevel] + props.topY;
 
       const selected = state.selectedBorderIndex!;
       if ("point" in selected) {
+        const { x, y } = map.snap(viewport, state.mouse!.x, state.mouse!.y, {
+          radius: distanceThreshold,
+          borderPoint: selected.point,
+        });
         const target = map.findNearestBorder(
           viewport,
           state.mouse!.x,
@@ -645,17 +763,51 @@ export const RerailEditor = (props: RerailEditorProps) => {
     });
   };
 
+  // applies the operators edited in the railway dialog, and returns the map
+  // with the railway info referring to the new operator if any
+  const applyRailwayDialogResult = (
+    railwayMap: RerailMap,
+    result: RailwayDialogResult,
+  ): [RerailMap, RailwayInfo] => {
+    for (const op of result.operators) {
+      railwayMap = railwayMap.renameOperator(op.id, op.name);
+    }
+    for (const id of result.removedOperators) {
+      railwayMap = railwayMap.removeOperator(id);
+    }
+    let info = result.info;
+    if (result.newOperator !== undefined) {
+      const newMapAndIndex = railwayMap.addOperator(result.newOperator);
+      railwayMap = newMapAndIndex.getMap();
+      info = {
+        ...info,
+        metadata: {
+          ...info.metadata,
+          operator: newMapAndIndex.getOperatorIndex(),
+        },
+      };
+    }
+    return [railwayMap, info];
+  };
+
   const onOpenRailwayConfig = async (id: number) => {
     const railwayMap = props.railwayMap;
     if (railwayMap === null) {
       return;
     }
     const railwayInfo = railwayMap.getRailwayInfo(id);
-    const newRailwayInfo = await railwayDialogRef.current!.open(railwayInfo);
-    if (newRailwayInfo === undefined) {
+    const result = await railwayDialogRef.current!.open(
+      railwayInfo,
+      railwayMap.operatorList().operators,
+    );
+    if (result === undefined) {
       return;
     }
-    props.setRailwayMap(railwayMap.setRailwayInfo(id, newRailwayInfo));
+    const [newRailwayMap, newRailwayInfo] = applyRailwayDialogResult(
+      railwayMap,
+      result,
+    );
+    props.setRailwayMap(newRailwayMap.setRailwayInfo(id, newRailwayInfo));
   };
 
   const onOpenStationList = (id: number) => {
@@ -664,7 +816,52 @@ export const RerailEditor = (props: RerailEditorProps) => {
       return;
     }
     const stationList = railwayMap.stationListOnRailway(id);
-    stationListDialogRef.current!.open(stationList);
+    stationListDialogRef.current!.open(id, stationList);
+  };
+
+  const onSetOfficialDistance = (
+    id: number,
+    index: number,
+    distance?: number,
+  ) => {
+    const railwayMap = props.railwayMap;
+    if (railwayMap === null) {
+      return undefined;
+    }
+    const newRailwayMap = railwayMap.setOfficialDistance(id, index, distance);
+    props.setRailwayMap(newRailwayMap);
+    return newRailwayMap.stationListOnRailway(id);
+  };
+
+  const hasOfficialDistances = (id: number) => {
+    const railwayMap = props.railwayMap;
+    return railwayMap !== null && railwayMap.hasOfficialDistances(id);
+  };
+
+  const onReverseRailway = (id: number) => {
+    const railwayMap = props.railwayMap;
+    if (railwayMap === null) {
+      return;
+    }
+    props.setRailwayMap(railwayMap.reverseRailway(id));
+  };
+
+  const onToggleRailwayClosed = (id: number) => {
+    const railwayMap = props.railwayMap;
+    if (railwayMap === null) {
+      return;
+    }
+    const closed = railwayMap.isRailwayClosed(id);
+    props.setRailwayMap(railwayMap.setRailwayClosed(id, !closed));
+  };
+
+  const onToggleRailwaySmooth = (id: number) => {
+    const railwayMap = props.railwayMap;
+    if (railwayMap === null) {
+      return;
+    }
+    const smooth = railwayMap.isRailwaySmooth(id);
+    props.setRailwayMap(railwayMap.setRailwaySmooth(id, !smooth));
   };
 
   const onDeleteRailway = (id: number) => {
@@ -764,6 +961,10 @@ export const RerailEditor = (props: RerailEditorProps) => {
             onSelect={onSelectRailway}
             onOpenRailwayConfig={onOpenRailwayConfig}
             onOpenStationList={onOpenStationList}
+            onToggleRailwayClosed={onToggleRailwayClosed}
+            onToggleRailwaySmooth={onToggleRailwaySmooth}
+            onReverseRailway={onReverseRailway}
+            hasOfficialDistances={hasOfficialDistances}
             onDeleteRailway={onDeleteRailway}
           />
         )}
@@ -800,7 +1001,11 @@ export const RerailEditor = (props: RerailEditorProps) => {
       </div>
       <StationDialog ref={stationDialogRef}></StationDialog>
       <RailwayDialog ref={railwayDialogRef}></RailwayDialog>
-      <StationListDialog ref={stationListDialogRef}></StationListDialog>
+      <StationListDialog
+        ref={stationListDialogRef}
+        onSetOfficialDistance={onSetOfficialDistance}
+      ></StationListDialog>
+      <StationChoiceDialog ref={stationChoiceDialogRef}></StationChoiceDialog>
     </div>
   );
 };
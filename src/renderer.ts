@@ -11,7 +11,9 @@ export function renderMap(
   let railColors = renderingInfo.rail_colors;
   let railWidth = renderingInfo.rail_width;
   let railStyle = renderingInfo.rail_style;
-  let railNumPoints = renderingInfo.rail_points_num;
+  let railNumPolylines = renderingInfo.rail_polylines_num;
+  let polylineStart = renderingInfo.polyline_start;
+  let polylineNumPoints = renderingInfo.polyline_points_num;
   let railPointX = renderingInfo.rail_points_x;
   let railPointY = renderingInfo.rail_points_y;
   let markerX = renderingInfo.marker_points_x;
@@ -21,8 +23,8 @@ export function renderMap(
   ctx.fillStyle = "white";
   ctx.fillRect(0, 0, width, height);
 
-  let p = 0;
-  for (let i = 0; i < railNumPoints.length; ++i) {
+  let l = 0;
+  for (let i = 0; i < railNumPolylines.length; ++i) {
     let color = railColors[i];
     ctx.lineWidth = railWidth[i];
     ctx.strokeStyle = `rgb(${color.r}, ${color.g}, ${color.b})`;
@@ -35,14 +37,16 @@ export function renderMap(
       // unexpected
       throw Error();
     }
-    for (let j = 0; j < railNumPoints[i]; j += 2) {
-      ctx.beginPath();
-      ctx.moveTo(railPointX[p], railPointY[p]);
-      p += 1;
-      ctx.lineTo(railPointX[p], railPointY[p]);
-      p += 1;
-      ctx.stroke();
+    ctx.beginPath();
+    for (let j = 0; j < railNumPolylines[i]; ++j) {
+      const start = polylineStart[l];
+      ctx.moveTo(railPointX[start], railPointY[start]);
+      for (let k = 1; k < polylineNumPoints[l]; ++k) {
+        ctx.lineTo(railPointX[start + k], railPointY[start + k]);
+      }
+      l += 1;
     }
+    ctx.stroke();
   }
 
   ctx.setLineDash([]);